@@ -16,7 +16,7 @@ use hal::gpio;
 use hal::pac::{interrupt, CorePeripherals, Peripherals};
 use hal::prelude::*;
 use hal::sercom::I2CMaster5;
-use hal_ext::alphanum::{Display, MultiDisplay, DISP_I2C_ADDR};
+use hal_ext::alphanum::{Display, MultiDisplay};
 use hal_ext::usb_serial::{self, USB_BUS, USB_SERIAL};
 
 #[cfg(debug_assertions)]
@@ -24,7 +24,6 @@ use cortex_m_log::log::{trick_init, Logger};
 #[cfg(debug_assertions)]
 use cortex_m_log::printer::semihosting;
 
-use ht16k33::HT16K33;
 use shared_bus::new_cortexm;
 
 const BUFFER_SIZE: usize = 512;
@@ -83,13 +82,7 @@ fn main() -> ! {
     > = i2c)
     .unwrap();
 
-    let displays = [
-        HT16K33::new(shared_bus.acquire_i2c(), DISP_I2C_ADDR),
-        HT16K33::new(shared_bus.acquire_i2c(), DISP_I2C_ADDR + 1),
-        HT16K33::new(shared_bus.acquire_i2c(), DISP_I2C_ADDR + 2),
-    ];
-
-    let mut multidisplay = MultiDisplay::new(displays);
+    let mut multidisplay = MultiDisplay::scan(|| shared_bus.acquire_i2c()).unwrap();
 
     let mut delay = Delay::new(core.SYST, &mut clocks);
 
@@ -1,9 +1,5 @@
 #![no_std]
 #![no_main]
-#![feature(default_alloc_error_handler)]
-
-#[macro_use]
-extern crate alloc;
 
 use metro_m4 as hal;
 use metro_m4_ext as hal_ext;
@@ -15,42 +11,55 @@ use panic_semihosting as _;
 
 use hal::clock::GenericClockController;
 use hal::delay::Delay;
+use hal::eic::pin::{ExtInt1, Sense};
+use hal::eic::EIC;
 use hal::entry;
 use hal::gpio;
-use hal::pac::{interrupt, CorePeripherals, Peripherals, SCB};
+use hal::pac::{interrupt, CorePeripherals, Peripherals, NVIC};
 use hal::prelude::*;
 use hal::sercom::I2CMaster5;
-use hal_ext::alphanum::{Display, MultiDisplay, DISP_I2C_ADDR};
+use hal_ext::alphanum::{Blink, Display, KeyEvent, Keypad, MultiDisplay, Recovery, DISP_I2C_ADDR};
+use hal_ext::rtc::calibration::Calibration;
+use hal_ext::rtc::command::{self, Command, LineBuffer, ParseError};
+use hal_ext::rtc::history::{self, History};
+use hal_ext::rtc::schedule::Schedule;
+use hal_ext::rtc::timer::{Button, Timer};
+use hal_ext::rtc::{alarm, internal};
+use hal_ext::rtc::{AnySource, Clock, Ds3231, InternalRtc, TimeSource, LINE_ENDING};
 use hal_ext::usb_serial::{self, USB_BUS, USB_SERIAL};
 
-use alloc_cortex_m::CortexMHeap;
+use core::fmt::Write;
+
 #[cfg(debug_assertions)]
 use cortex_m_log::log::{trick_init, Logger};
 #[cfg(debug_assertions)]
 use cortex_m_log::printer::semihosting;
 
-use ds323x::{Datelike, Ds323x, Rtcc, Timelike};
-use ht16k33::HT16K33;
 use shared_bus::new_cortexm;
 
 const BUFFER_SIZE: usize = 512;
 static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
 static mut BUFF_LEN: usize = 0;
-static mut WRITE_IDX: usize = 0;
+static mut LINE: LineBuffer<BUFFER_SIZE> = LineBuffer::new();
+static mut COMMAND: Option<Command> = None;
+
+/// DS3231 INT/SQW, wired to D2 when the board has one
+static mut ALARM_INT: Option<ExtInt1<gpio::Pb17<gpio::Interrupt<gpio::PullUp>>>> = None;
+/// How long the display blinks for once an alarm goes off
+const RINGING_TICKS: u16 = 100;
+const TICK_MS: u16 = 100;
 
-const BUFFER_ADDR: u32 = 0x0;
-const BUFFER_LEN_ADDR: u32 = BUFFER_ADDR + BUFFER_SIZE as u32;
+/// Keys wired to the first display's HT16K33, see `Keypad`
+const KEY_START_STOP: u8 = 0;
+const KEY_LAP_RESET: u8 = 1;
+const KEY_MODE: u8 = 2;
 
-#[global_allocator]
-static ALLOCATOR: CortexMHeap = CortexMHeap::empty();
+/// Times a reply waits for room in the serial buffer, ~100us each, before
+/// it's dropped
+const SERIAL_RETRIES: u32 = 1000;
 
 #[entry]
 fn main() -> ! {
-    // Initialize the allocator BEFORE you use it
-    let start = cortex_m_rt::heap_start() as usize;
-    let size = 1024; // in bytes
-    unsafe { ALLOCATOR.init(start, size) }
-
     #[cfg(debug_assertions)]
     {
         let logger = Logger {
@@ -99,13 +108,20 @@ fn main() -> ! {
         pins.flash_io3,
     );
 
-    // Populate buffer from what's stored in flash
+    // INT/SQW is open drain and active low
+    let gclk1 = clocks.gclk1();
+    let eic_clock = clocks.eic(&gclk1).unwrap();
+    let mut eic = EIC::init(&mut peripherals.MCLK, eic_clock, peripherals.EIC);
+    let mut alarm_int = ExtInt1::new(pins.d2.into_pull_up_interrupt(&mut pins.port));
+    alarm_int.sense(&mut eic, Sense::FALL);
+    alarm_int.enable_interrupt(&mut eic);
+    let _eic = eic.finalize();
+
     unsafe {
-        flash.read(BUFFER_ADDR, &mut BUFFER[..]);
+        ALARM_INT = Some(alarm_int);
 
-        let mut buff = [0; 4];
-        flash.read(BUFFER_LEN_ADDR, &mut buff);
-        BUFF_LEN = usize::from_le_bytes(buff);
+        core.NVIC.set_priority(interrupt::EIC_EXTINT_1, 1);
+        NVIC::unmask(interrupt::EIC_EXTINT_1);
     }
 
     let i2c = hal::i2c_master(
@@ -124,126 +140,309 @@ fn main() -> ! {
     > = i2c)
     .unwrap();
 
-    let displays = [
-        HT16K33::new(shared_bus.acquire_i2c(), DISP_I2C_ADDR),
-        HT16K33::new(shared_bus.acquire_i2c(), DISP_I2C_ADDR + 1),
-        HT16K33::new(shared_bus.acquire_i2c(), DISP_I2C_ADDR + 2),
-    ];
+    let mut multidisplay = MultiDisplay::scan(|| shared_bus.acquire_i2c()).unwrap();
+    multidisplay.set_recovery(Recovery::Reinitialize { retries: 3 });
 
-    let mut multidisplay = MultiDisplay::new(displays);
+    // Optional stopwatch keys, polled every tick
+    let mut keypad = Keypad::new(shared_bus.acquire_i2c(), DISP_I2C_ADDR, false).ok();
 
-    let mut clock = Ds323x::new_ds3231(shared_bus.acquire_i2c());
+    // Falls back to the SAMD51's RTC on boards without a DS3231
+    let rtc = peripherals.RTC;
+    let mclk = &mut peripherals.MCLK;
+    let osc32kctrl = &mut peripherals.OSC32KCTRL;
+    let nvic = &mut core.NVIC;
+    let source = AnySource::detect(Ds3231::new(shared_bus.acquire_i2c()), || {
+        InternalRtc::new(rtc, mclk, osc32kctrl, nvic)
+    });
+
+    let mut clock = Clock::new(source);
+
+    // The RTC holds UTC, shown in the zone last set with `tz set`
+    clock.load_time_zone(&mut flash);
+
+    // Drift correction from the last `cal end` or `cal offset`
+    if let Some(calibration) = Calibration::load(&mut flash) {
+        if let Err(e) = clock.set_calibration(calibration) {
+            #[cfg(debug_assertions)]
+            log::error!("{:?}", e);
+        }
+    }
 
-    // Only needed on first run, or if batter isn't inserted
-    //let now = NaiveDateTime::parse_from_str("2021-01-26 11:40:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    // Only needed on first run, or if batter isn't inserted. UTC
+    //let now = hal_ext::rtc::NaiveDate::from_ymd(2021, 1, 26).and_hms(16, 40, 0);
     //clock.set_datetime(&now).unwrap();
 
-    let mut text_buf: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
-    let mut text_len = 0usize;
+    // Screens set up with `screen add`, time and date until then. The message
+    // is saved with the playlist, one saved to sector 0 by older versions of
+    // this example is ignored and has to be sent again.
+    let mut schedule = Schedule::load(&mut flash).unwrap_or_else(Schedule::default_playlist);
+    // Countdown and stopwatch, picking up where they were before a reset
+    let mut timer = Timer::load(&mut flash).unwrap_or_default();
+    // Up to an hour of samples can be lost on a reset, see `history`
+    let mut history = History::load(&mut flash, history::DEFAULT_INTERVAL_SECS).unwrap_or_default();
+    let mut ringing = 0u16;
 
     loop {
-        let mut last_second = 0;
-        let mut delay_total = 0;
-
-        while delay_total < 5000 {
-            let time = clock.get_time().unwrap();
-            let (is_pm, hour) = time.hour12();
-            let minute = time.minute();
-            let second = time.second();
-            let am_pm = if is_pm { "PM" } else { "AM" };
+        handle_command(
+            &mut clock,
+            &mut schedule,
+            &mut timer,
+            &mut history,
+            &mut flash,
+        );
+        handle_message(&mut schedule, &mut flash);
+
+        if let Some(keypad) = keypad.as_mut() {
+            handle_keys(keypad, &mut clock, &mut timer, &mut flash);
+        }
 
-            if second != last_second {
-                last_second = second;
+        check_countdown(&mut clock, &mut timer, &mut flash, &mut ringing);
+        sample_temperature(&mut clock, &mut history, &mut flash);
+        check_alarms(&mut clock, &mut multidisplay, &mut ringing);
 
-                let time_str = format!("{:0>2?}{:0>2}{:0>2} {}   ", hour, minute, second, am_pm);
+        schedule.tick(TICK_MS as u32, multidisplay.cells());
 
-                let dot_flags = [
-                    false, true, false, true, false, false, false, false, false, false, false,
-                    false,
-                ];
+        // The playlist shows unless the timer is in countdown or stopwatch
+        // mode
+        let text = match timer.render(&mut clock, multidisplay.cells()) {
+            Ok(Some(text)) => Ok(text),
+            Ok(None) => schedule.render(&mut clock, &timer.countdown, multidisplay.cells()),
+            Err(e) => Err(e),
+        };
 
-                if let Err(e) = multidisplay.display(time_str.as_bytes(), Some(&dot_flags)) {
+        // Unchanged drivers are skipped, so it's fine to display every tick
+        match text {
+            Ok(text) => {
+                if let Err(e) = multidisplay.display(text.text(), Some(text.dots())) {
                     #[cfg(debug_assertions)]
-                    log::error!("{:?}", e);
-
-                    SCB::sys_reset();
+                    log::error!("{:?}, health: {:?}", e, multidisplay.health());
                 }
             }
-
-            delay.delay_ms(100u16);
-            delay_total += 100;
+            Err(e) => {
+                #[cfg(debug_assertions)]
+                log::error!("{:?}", e);
+            }
         }
 
-        let mut last_temp = 0;
-        delay_total = 0;
-
-        while delay_total < 5000 {
-            let temp = (clock.get_temperature().unwrap() * 1.8 + 32.0) as u32;
-            let date = clock.get_date().unwrap();
-            let month = date.month();
-            let day = date.day();
-            let weekday = date.weekday();
-            let weekday_str = format!("{}", weekday);
-
-            if temp != last_temp {
-                last_temp = temp;
-
-                let temp_str = format!(
-                    "{} {:0>2}{:0>2} {: >2}F",
-                    &weekday_str[0..3],
-                    month,
-                    day,
-                    temp
-                );
-
-                let dot_flags = [
-                    false, false, false, false, false, true, false, false, false, false, false,
-                    false,
-                ];
-
-                if let Err(e) = multidisplay.display(temp_str.as_bytes(), Some(&dot_flags)) {
-                    #[cfg(debug_assertions)]
-                    log::error!("{:?}", e);
+        delay.delay_ms(TICK_MS);
+    }
+}
 
-                    SCB::sys_reset();
+/// Runs the last command received over serial and replies with the result
+fn handle_command<S: TimeSource>(
+    clock: &mut Clock<S>,
+    schedule: &mut Schedule,
+    timer: &mut Timer,
+    history: &mut History,
+    flash: &mut hal_ext::flash::QspiFlash,
+) {
+    let command = cortex_m::interrupt::free(|_| unsafe { COMMAND.take() });
+
+    if let Some(command) = command {
+        let mut reply = SerialWriter;
+        command::execute(clock, schedule, timer, history, flash, &command, &mut reply);
+
+        let _ = reply.write_str(LINE_ENDING);
+    }
+}
+
+/// Writes straight to the serial port, waiting for room, so replies can run
+/// over many lines, ie. the temperature history
+struct SerialWriter;
+
+impl Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let mut bytes = s.as_bytes();
+        let mut retries = 0;
+
+        while !bytes.is_empty() {
+            // Interrupts run in between, so the USB interrupt can send
+            let written = cortex_m::interrupt::free(|_| unsafe {
+                USB_SERIAL
+                    .as_mut()
+                    .map(|serial| serial.write(bytes).unwrap_or(0))
+            });
+
+            match written {
+                // No serial port, nothing to wait for
+                None => return Ok(()),
+                Some(0) if retries == SERIAL_RETRIES => return Err(core::fmt::Error),
+                Some(0) => {
+                    retries += 1;
+                    cortex_m::asm::delay(12_000);
+                }
+                Some(n) => {
+                    bytes = &bytes[n..];
+                    retries = 0;
                 }
             }
+        }
 
-            delay.delay_ms(100u16);
-            delay_total += 100;
+        Ok(())
+    }
+}
+
+/// Samples the temperature on `history`'s interval, checkpointing it to flash
+/// now and then
+fn sample_temperature<S: TimeSource>(
+    clock: &mut Clock<S>,
+    history: &mut History,
+    flash: &mut hal_ext::flash::QspiFlash,
+) {
+    match history.sample(clock) {
+        Ok(true) if history.needs_checkpoint() => history.save(flash),
+        Ok(_) => {}
+        Err(e) => {
+            #[cfg(debug_assertions)]
+            log::error!("{:?}", e);
         }
+    }
+}
 
-        cortex_m::interrupt::free(|_| unsafe {
-            if BUFF_LEN > 0 {
-                text_buf = BUFFER;
-                text_len = BUFF_LEN;
+/// Shows the last plain line received over serial on the message screen
+fn handle_message(schedule: &mut Schedule, flash: &mut hal_ext::flash::QspiFlash) {
+    let mut message = [0; BUFFER_SIZE];
 
-                flash.erase_sector(0x0);
+    let len = cortex_m::interrupt::free(|_| unsafe {
+        let len = BUFF_LEN;
+        message[..len].copy_from_slice(&BUFFER[..len]);
+        BUFF_LEN = 0;
+        len
+    });
 
-                // Save new word to memory
-                flash.write(BUFFER_ADDR, &text_buf[..256]);
-                flash.write(BUFFER_ADDR + 256, &text_buf[256..]);
-                flash.write(BUFFER_LEN_ADDR, &usize::to_le_bytes(text_len)[..]);
+    if len == 0 {
+        return;
+    }
+
+    // Unlike commands, messages only get a reply when something is off
+    let mut reply = SerialWriter;
 
-                BUFF_LEN = 0;
+    match schedule.set_message(&message[..len]) {
+        Ok(kept) => {
+            schedule.save(flash);
+
+            if kept < len {
+                let _ = write!(reply, "message cut to {} characters{}", kept, LINE_ENDING);
             }
-        });
+        }
+        Err(e) => {
+            let _ = write!(reply, "ERR {:?}{}", e, LINE_ENDING);
+        }
+    }
+}
 
-        let mut n = 0;
-        while n < 2 {
-            if text_len > 0 {
-                let text = unsafe { core::str::from_utf8_unchecked(&text_buf[0..text_len]) };
+/// Works the timer from the keypad, on key down
+fn handle_keys<S, I2C, E>(
+    keypad: &mut Keypad<I2C>,
+    clock: &mut Clock<S>,
+    timer: &mut Timer,
+    flash: &mut hal_ext::flash::QspiFlash,
+) where
+    S: TimeSource,
+    I2C: embedded_hal::blocking::i2c::Write<Error = E>
+        + embedded_hal::blocking::i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    let events = match keypad.poll() {
+        Ok(events) => events,
+        Err(e) => {
+            #[cfg(debug_assertions)]
+            log::error!("{:?}", e);
+            return;
+        }
+    };
+
+    for event in events {
+        let button = match event {
+            KeyEvent::Pressed(KEY_START_STOP) => Button::StartStop,
+            KeyEvent::Pressed(KEY_LAP_RESET) => Button::LapReset,
+            KeyEvent::Pressed(KEY_MODE) => Button::Mode,
+            _ => continue,
+        };
 
-                if let Err(e) = multidisplay.marquee(text, &mut delay, 200u8, true) {
-                    #[cfg(debug_assertions)]
-                    log::error!("{:?}", e);
+        let result = match clock.datetime() {
+            Ok(now) => timer.press(button, &now),
+            Err(e) => {
+                #[cfg(debug_assertions)]
+                log::error!("{:?}", e);
+                return;
+            }
+        };
 
-                    SCB::sys_reset();
-                }
+        match result {
+            // Laps aren't saved
+            Ok(()) if button != Button::LapReset || !timer.stopwatch.is_running() => {
+                timer.save(flash)
+            }
+            Ok(()) => {}
+            Err(e) => {
+                #[cfg(debug_assertions)]
+                log::error!("{:?}", e);
+            }
+        }
+    }
+}
+
+/// Rings like an alarm once the countdown reaches its target
+fn check_countdown<S: TimeSource>(
+    clock: &mut Clock<S>,
+    timer: &mut Timer,
+    flash: &mut hal_ext::flash::QspiFlash,
+    ringing: &mut u16,
+) {
+    match clock.datetime() {
+        Ok(now) => {
+            if timer.countdown.take_finished(&now) {
+                *ringing = RINGING_TICKS;
+                timer.save(flash);
             }
+        }
+        Err(e) => {
+            #[cfg(debug_assertions)]
+            log::error!("{:?}", e);
+        }
+    }
+}
+
+/// Blinks the display for a while when an alarm goes off
+fn check_alarms<S, I2C, E>(
+    clock: &mut Clock<S>,
+    multidisplay: &mut MultiDisplay<I2C>,
+    ringing: &mut u16,
+) where
+    S: TimeSource,
+    I2C: embedded_hal::blocking::i2c::Write<Error = E>
+        + embedded_hal::blocking::i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    let result = clock.handle_alarms(|slot| {
+        #[cfg(debug_assertions)]
+        log::info!("{:?} went off", slot);
+
+        // A piezo on a PWM pin could be started here as well
+        *ringing = RINGING_TICKS;
+    });
+
+    if let Err(e) = result {
+        #[cfg(debug_assertions)]
+        log::error!("{:?}", e);
+    }
 
-            n += 1;
+    let blink = match *ringing {
+        0 => return,
+        1 => Blink::Off,
+        RINGING_TICKS => Blink::TwoHz,
+        _ => {
+            *ringing -= 1;
+            return;
         }
+    };
+
+    *ringing -= 1;
+
+    if let Err(e) = multidisplay.set_blink(blink) {
+        #[cfg(debug_assertions)]
+        log::error!("{:?}, health: {:?}", e, multidisplay.health());
     }
 }
 
@@ -252,32 +451,23 @@ fn poll_usb() {
         USB_BUS.as_mut().map(|usb_dev| {
             USB_SERIAL.as_mut().map(|serial| {
                 if usb_dev.poll(&mut [serial]) {
-                    // Make the other side happy
                     let mut buf = [0u8; 128];
                     if let Ok(n) = serial.read(&mut buf) {
-                        let end = buf[n - 1] == 0;
-                        let through = if end { n - 1 } else { n };
-
-                        // Dont panic if we write more than buffer can hold
-                        // just return and display up till last value
-                        if WRITE_IDX + n >= BUFFER_SIZE {
-                            WRITE_IDX = 0;
-                            BUFF_LEN = BUFFER_SIZE;
-
-                            return;
-                        }
-
-                        if through > 0 {
-                            BUFFER[WRITE_IDX..WRITE_IDX + through]
-                                .swap_with_slice(&mut buf[0..through]);
-                        }
-
-                        if end {
-                            BUFF_LEN = WRITE_IDX + through;
-                            WRITE_IDX = 0;
-                        } else {
-                            WRITE_IDX += through;
-                        }
+                        LINE.feed(&buf[..n], |line| {
+                            match line.map(|line| (line, Command::parse(line))) {
+                                Ok((_, Ok(command))) => COMMAND = Some(command),
+                                Ok((line, Err(ParseError::UnknownCommand))) => {
+                                    // Anything else is the new message
+                                    BUFFER[..line.len()].copy_from_slice(line);
+                                    BUFF_LEN = line.len();
+                                }
+                                Ok((_, Err(e))) | Err(e) => {
+                                    let _ = serial.write(b"ERR ");
+                                    let _ = serial.write(e.as_str().as_bytes());
+                                    let _ = serial.write(LINE_ENDING.as_bytes());
+                                }
+                            }
+                        });
                     }
                 }
             });
@@ -304,3 +494,20 @@ fn USB_SOF_HSOF() {
 fn USB_OTHER() {
     poll_usb();
 }
+
+#[interrupt]
+fn EIC_EXTINT_1() {
+    unsafe {
+        if let Some(alarm_int) = ALARM_INT.as_mut() {
+            if alarm_int.is_interrupt() {
+                alarm_int.clear_interrupt();
+                alarm::notify();
+            }
+        }
+    }
+}
+
+#[interrupt]
+fn RTC() {
+    internal::on_interrupt();
+}
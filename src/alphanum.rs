@@ -1,41 +1,773 @@
-use adafruit_alphanum4::{AlphaNum4, AsciiChar, Index};
 use embedded_hal::blocking::delay::DelayMs;
 use embedded_hal::blocking::i2c;
-use ht16k33::HT16K33;
+use heapless::Vec;
+use ht16k33::{Dimming, LedLocation, HT16K33};
+
+#[cfg(feature = "async")]
+mod async_display;
+mod backpack;
+#[cfg(feature = "dma")]
+pub mod dma;
+pub mod effects;
+pub mod font;
+pub mod keypad;
+pub mod matrix;
+pub mod segments;
+pub mod seven_segment;
+pub mod text;
+pub mod zones;
+
+#[cfg(feature = "async")]
+pub use async_display::AsyncDisplay;
+pub use backpack::Backpack;
+#[cfg(feature = "dma")]
+pub use dma::DisplayDma;
+pub use effects::Transition;
+pub use keypad::{KeyEvent, Keypad};
+pub use segments::{Animation, Keyframe, Segment, SegmentFrame};
+pub use zones::Zones;
 
 pub const DISP_I2C_ADDR: u8 = 112;
+pub const MAX_BRIGHTNESS: u8 = 15;
 const LEDS_PER_DRIVER: usize = 4;
 const MAX_DRIVERS: usize = 10;
+const HT16K33_ADDRESSES: u8 = 8;
+const OSCILLATOR_ON: u8 = 0x21;
+const RAM_SIZE: usize = 16;
+
+/// Blink rate of the HT16K33 display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Blink {
+    Off,
+    TwoHz,
+    OneHz,
+    HalfHz,
+}
+
+impl Blink {
+    fn as_display(self) -> ht16k33::Display {
+        match self {
+            Blink::Off => ht16k33::Display::ON,
+            Blink::TwoHz => ht16k33::Display::TWO_HZ,
+            Blink::OneHz => ht16k33::Display::ONE_HZ,
+            Blink::HalfHz => ht16k33::Display::HALF_HZ,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DriverSettings {
+    brightness: u8,
+    blink: Blink,
+}
+
+impl Default for DriverSettings {
+    fn default() -> Self {
+        DriverSettings {
+            brightness: MAX_BRIGHTNESS,
+            blink: Blink::Off,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    /// The driver at `index` failed, after any recovery attempts
+    Driver {
+        index: usize,
+        error: E,
+    },
+    /// No HT16K33 responded on the bus
+    NoDrivers,
+    /// More than `MAX_DRIVERS` drivers were given
+    TooManyDrivers,
+    /// The previous DMA frame is still going out
+    #[cfg(feature = "dma")]
+    DmaBusy,
+}
+
+/// What to do when a driver fails to take a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Fail the write straight away
+    Disabled,
+    /// Re-initialize the failed driver and retry the write up to `retries`
+    /// times, carrying on with the rest of the chain if it still fails
+    Reinitialize { retries: u8 },
+}
 
-pub struct MultiDisplay<I2C, const N: usize> {
-    drivers: [HT16K33<I2C>; N],
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Ok,
+    /// Last write only succeeded after re-initializing the driver
+    Recovered,
+    /// Last write failed
+    Failed,
 }
 
-impl<'a, I2C, E, const N: usize> MultiDisplay<I2C, N>
+#[derive(Debug, Clone, Copy)]
+pub struct DriverHealth {
+    pub status: Health,
+    /// Total number of failed writes
+    pub errors: u32,
+}
+
+impl Default for DriverHealth {
+    fn default() -> Self {
+        DriverHealth {
+            status: Health::Ok,
+            errors: 0,
+        }
+    }
+}
+
+/// How content is laid out across the drivers of a chain
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    /// Kind of backpack each driver is on
+    backpacks: [Backpack; MAX_DRIVERS],
+    /// Text runs from the last driver to the first
+    reversed: bool,
+    /// Drivers mounted upside down
+    rotated: [bool; MAX_DRIVERS],
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            backpacks: [Backpack::default(); MAX_DRIVERS],
+            reversed: false,
+            rotated: [false; MAX_DRIVERS],
+        }
+    }
+}
+
+impl Layout {
+    /// Number of character cells across a chain of `len` drivers
+    fn cells(&self, len: usize) -> usize {
+        self.backpacks[..len]
+            .iter()
+            .map(|backpack| backpack.cells())
+            .sum()
+    }
+
+    /// Index, in a chain of `len` drivers, of the driver showing the
+    /// `logical`th part of the content
+    fn physical_index(&self, len: usize, logical: usize) -> usize {
+        if self.reversed {
+            len - 1 - logical
+        } else {
+            logical
+        }
+    }
+
+    /// Renders `buffer` into `frames`, the display RAM of each driver in the
+    /// chain
+    fn render_text(
+        &self,
+        frames: &mut [[u8; RAM_SIZE]],
+        buffer: &[u8],
+        enable_dot: Option<&[bool]>,
+    ) {
+        let mut text = buffer;
+        let mut dots = enable_dot.unwrap_or(&[]);
+
+        for logical in 0..frames.len() {
+            let index = self.physical_index(frames.len(), logical);
+            let backpack = self.backpacks[index];
+
+            let used = backpack.render_text(&mut frames[index], text, dots);
+
+            if self.rotated[index] {
+                backpack.rotate(&mut frames[index]);
+            }
+
+            // Dot flags go with the text, a 7 segment colon has one too
+            text = &text[used..];
+            dots = dots.get(used..).unwrap_or(&[]);
+        }
+    }
+
+    /// Renders raw segments into `frames`, like `render_text`
+    fn render_segments(&self, frames: &mut [[u8; RAM_SIZE]], cells: &[u16]) {
+        let mut cell = 0;
+
+        for logical in 0..frames.len() {
+            let index = self.physical_index(frames.len(), logical);
+            let backpack = self.backpacks[index];
+
+            backpack.render_segments(&mut frames[index], cells.get(cell..).unwrap_or(&[]));
+
+            if self.rotated[index] {
+                backpack.rotate(&mut frames[index]);
+            }
+
+            cell += backpack.cells();
+        }
+    }
+}
+
+/// Display RAM as last written to each driver, `None` if unknown, so
+/// unchanged drivers can be skipped
+#[derive(Debug, Clone, Copy)]
+struct Shadow([Option<[u8; RAM_SIZE]>; MAX_DRIVERS]);
+
+impl Shadow {
+    fn new() -> Self {
+        Shadow([None; MAX_DRIVERS])
+    }
+
+    /// Whether the driver at `index` is known to show `ram`
+    fn is_current(&self, index: usize, ram: &[u8; RAM_SIZE]) -> bool {
+        self.0[index].as_ref() == Some(ram)
+    }
+
+    fn set(&mut self, index: usize, ram: Option<[u8; RAM_SIZE]>) {
+        self.0[index] = ram;
+    }
+
+    fn clear(&mut self) {
+        self.0 = [None; MAX_DRIVERS];
+    }
+}
+
+pub struct MultiDisplay<I2C> {
+    drivers: Vec<HT16K33<I2C>, MAX_DRIVERS>,
+    /// I2C address of each driver
+    addresses: Vec<u8, MAX_DRIVERS>,
+    settings: [DriverSettings; MAX_DRIVERS],
+    health: [DriverHealth; MAX_DRIVERS],
+    recovery: Recovery,
+    shadow: Shadow,
+    layout: Layout,
+}
+
+impl<'a, I2C, E> MultiDisplay<I2C>
 where
     I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
     E: core::fmt::Debug,
 {
-    pub fn new(mut drivers: [HT16K33<I2C>; N]) -> MultiDisplay<I2C, N> {
-        if drivers.len() > MAX_DRIVERS {
-            panic!("Can't use more than 10 drivers with this struct")
+    /// Chains the HT16K33s at `addresses`, in order. `acquire` is called
+    /// once per address, ie. `|| shared_bus.acquire_i2c()`.
+    pub fn new<F>(mut acquire: F, addresses: &[u8]) -> Result<MultiDisplay<I2C>, Error<E>>
+    where
+        F: FnMut() -> I2C,
+    {
+        let addresses = Vec::from_slice(addresses).map_err(|_| Error::TooManyDrivers)?;
+        let mut chain = Vec::new();
+
+        for (index, address) in addresses.iter().enumerate() {
+            let mut driver = HT16K33::new(acquire(), *address);
+            init_driver(&mut driver).map_err(|error| Error::Driver { index, error })?;
+
+            let _ = chain.push(driver);
+        }
+
+        Ok(Self::from_chain(chain, addresses))
+    }
+
+    /// Probes the eight HT16K33 addresses, starting at `DISP_I2C_ADDR`, and
+    /// chains every driver that responds in address order. `acquire` is
+    /// called once per address, ie. `|| shared_bus.acquire_i2c()`.
+    pub fn scan<F>(mut acquire: F) -> Result<MultiDisplay<I2C>, Error<E>>
+    where
+        F: FnMut() -> I2C,
+    {
+        let mut chain = Vec::new();
+        let mut addresses = Vec::new();
+
+        for address in DISP_I2C_ADDR..DISP_I2C_ADDR + HT16K33_ADDRESSES {
+            let mut i2c = acquire();
+
+            // Turning the oscillator on is harmless and gets ACKed by any
+            // HT16K33 listening on this address
+            if i2c.write(address, &[OSCILLATOR_ON]).is_err() {
+                continue;
+            }
+
+            log::info!("Found driver at {:#x}", address);
+
+            let index = chain.len();
+            let mut driver = HT16K33::new(i2c, address);
+            init_driver(&mut driver).map_err(|error| Error::Driver { index, error })?;
+
+            let _ = chain.push(driver);
+            let _ = addresses.push(address);
         }
 
-        for driver in drivers.iter_mut() {
-            driver.initialize().unwrap();
-            driver.set_display(ht16k33::Display::ON).unwrap();
+        if chain.is_empty() {
+            return Err(Error::NoDrivers);
         }
 
+        Ok(Self::from_chain(chain, addresses))
+    }
+
+    fn from_chain(
+        drivers: Vec<HT16K33<I2C>, MAX_DRIVERS>,
+        addresses: Vec<u8, MAX_DRIVERS>,
+    ) -> MultiDisplay<I2C> {
         log::info!("{} drivers initialized", drivers.len());
 
-        MultiDisplay { drivers }
+        MultiDisplay {
+            drivers,
+            addresses,
+            settings: [DriverSettings::default(); MAX_DRIVERS],
+            health: [DriverHealth::default(); MAX_DRIVERS],
+            recovery: Recovery::Disabled,
+            shadow: Shadow::new(),
+            layout: Layout::default(),
+        }
+    }
+
+    /// Number of drivers in the chain
+    pub fn len(&self) -> usize {
+        self.drivers.len()
+    }
+
+    /// I2C address of each driver, in chain order
+    pub fn addresses(&self) -> &[u8] {
+        &self.addresses
+    }
+
+    /// Number of character cells across the chain
+    pub fn cells(&self) -> usize {
+        self.layout.cells(self.drivers.len())
+    }
+
+    /// Cells of the driver at `index` within the chain, ie. to place zones
+    pub fn driver_cells(&self, index: usize) -> Option<core::ops::Range<usize>> {
+        if index >= self.drivers.len() {
+            return None;
+        }
+
+        let start = (0..self.drivers.len())
+            .map(|logical| self.layout.physical_index(self.drivers.len(), logical))
+            .take_while(|physical| *physical != index)
+            .map(|physical| self.layout.backpacks[physical].cells())
+            .sum();
+
+        Some(start..start + self.layout.backpacks[index].cells())
+    }
+
+    /// Sets the kind of backpack every driver is on, `Backpack::AlphaNum4`
+    /// by default. Takes effect on the next update.
+    pub fn set_backpack(&mut self, backpack: Backpack) {
+        self.layout.backpacks = [backpack; MAX_DRIVERS];
+        self.force_refresh();
+    }
+
+    /// Sets the kind of backpack of each driver, in chain order, for chains
+    /// mixing backpacks. Drivers past the end of `backpacks` are left as is.
+    pub fn set_backpacks(&mut self, backpacks: &[Backpack]) {
+        for (current, backpack) in self.layout.backpacks.iter_mut().zip(backpacks) {
+            *current = *backpack;
+        }
+
+        self.force_refresh();
+    }
+
+    /// Sets the kind of backpack of a single driver. Indices past the end of
+    /// the chain are ignored.
+    pub fn set_driver_backpack(&mut self, index: usize, backpack: Backpack) {
+        if index < self.drivers.len() {
+            self.layout.backpacks[index] = backpack;
+            self.shadow.set(index, None);
+        }
+    }
+
+    /// Runs text from the last driver in the chain to the first, for chains
+    /// wired right to left
+    pub fn set_reversed(&mut self, reversed: bool) {
+        self.layout.reversed = reversed;
+        self.force_refresh();
+    }
+
+    /// Rotates every driver 180°, for backpacks mounted upside down
+    pub fn set_rotated(&mut self, rotated: bool) {
+        self.layout.rotated = [rotated; MAX_DRIVERS];
+        self.force_refresh();
+    }
+
+    /// Rotates a single driver 180°. Indices past the end of the chain are
+    /// ignored.
+    pub fn set_driver_rotated(&mut self, index: usize, rotated: bool) {
+        if index < self.drivers.len() {
+            self.layout.rotated[index] = rotated;
+            self.shadow.set(index, None);
+        }
+    }
+
+    /// Kind of backpack the driver at `index` is on
+    pub fn backpack(&self, index: usize) -> Option<Backpack> {
+        self.layout.backpacks[..self.drivers.len()]
+            .get(index)
+            .copied()
+    }
+
+    pub fn set_recovery(&mut self, recovery: Recovery) {
+        self.recovery = recovery;
+    }
+
+    /// Makes the next update write every driver, even if its content didn't
+    /// change, ie. after a driver was power cycled.
+    pub fn force_refresh(&mut self) {
+        self.shadow.clear();
+    }
+
+    /// Health of each driver in the chain
+    pub fn health(&self) -> &[DriverHealth] {
+        &self.health[..self.drivers.len()]
+    }
+
+    /// Sets the dimming level (0 - 15) of every driver
+    pub fn set_brightness(&mut self, level: u8) -> Result<(), Error<E>> {
+        for index in 0..self.drivers.len() {
+            self.set_driver_brightness(index, level)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the blink rate of every driver
+    pub fn set_blink(&mut self, blink: Blink) -> Result<(), Error<E>> {
+        for index in 0..self.drivers.len() {
+            self.set_driver_blink(index, blink)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the dimming level (0 - 15) of a single driver. Indices past the
+    /// end of the chain are ignored.
+    pub fn set_driver_brightness(&mut self, index: usize, level: u8) -> Result<(), Error<E>> {
+        let level = level.min(MAX_BRIGHTNESS);
+
+        if let Some(driver) = self.drivers.get_mut(index) {
+            driver
+                .set_dimming(Dimming::from_bits_truncate(level))
+                .map_err(|error| Error::Driver { index, error })?;
+            self.settings[index].brightness = level;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the blink rate of a single driver. Indices past the end of the
+    /// chain are ignored.
+    pub fn set_driver_blink(&mut self, index: usize, blink: Blink) -> Result<(), Error<E>> {
+        if let Some(driver) = self.drivers.get_mut(index) {
+            driver
+                .set_display(blink.as_display())
+                .map_err(|error| Error::Driver { index, error })?;
+            self.settings[index].blink = blink;
+        }
+
+        Ok(())
+    }
+
+    /// Current dimming level of the driver at `index`
+    pub fn brightness(&self, index: usize) -> Option<u8> {
+        self.settings[..self.drivers.len()]
+            .get(index)
+            .map(|settings| settings.brightness)
+    }
+
+    /// Current blink rate of the driver at `index`
+    pub fn blink(&self, index: usize) -> Option<Blink> {
+        self.settings[..self.drivers.len()]
+            .get(index)
+            .map(|settings| settings.blink)
+    }
+
+    /// Updates the driver buffers with `buffer`, without writing them out
+    fn render(&mut self, buffer: &[u8], enable_dot: Option<&[bool]>) {
+        let mut frames = [[0; RAM_SIZE]; MAX_DRIVERS];
+        let frames = &mut frames[..self.drivers.len()];
+
+        self.layout.render_text(frames, buffer, enable_dot);
+        self.load_frames(frames);
+    }
+
+    /// Updates the driver buffers with raw segments, without writing them out
+    fn render_segments(&mut self, cells: &[u16]) {
+        let mut frames = [[0; RAM_SIZE]; MAX_DRIVERS];
+        let frames = &mut frames[..self.drivers.len()];
+
+        self.layout.render_segments(frames, cells);
+        self.load_frames(frames);
+    }
+
+    fn load_frames(&mut self, frames: &[[u8; RAM_SIZE]]) {
+        for (driver, ram) in self.drivers.iter_mut().zip(frames) {
+            load_ram(driver, ram);
+        }
+    }
+
+    /// Shows a frame of raw segments, ie. from an `effects::Transition`.
+    /// Cells past the end of the frame are cleared.
+    pub fn display_frame(&mut self, frame: &SegmentFrame) -> Result<(), Error<E>> {
+        self.render_segments(frame.cells());
+        self.flush()
+    }
+
+    /// Shows every zone composited into one frame, cells outside any zone
+    /// are cleared
+    pub fn display_zones(&mut self, zones: &Zones) -> Result<(), Error<E>> {
+        let mut frame = SegmentFrame::new(self.cells());
+        zones.render(&mut frame);
+
+        self.display_frame(&frame)
+    }
+
+    /// Plays `transition` to the end, waiting `delay_ms` between frames
+    pub fn play<T, Delay, UXX>(
+        &mut self,
+        transition: &mut T,
+        delay: &mut Delay,
+        delay_ms: UXX,
+    ) -> Result<(), Error<E>>
+    where
+        T: Transition,
+        Delay: DelayMs<UXX>,
+        UXX: Copy,
+    {
+        let mut frame = SegmentFrame::new(self.cells());
+
+        while transition.next_frame(&mut frame) {
+            self.display_frame(&frame)?;
+
+            delay.delay_ms(delay_ms);
+        }
+
+        Ok(())
+    }
+
+    /// Plays the keyframes of an animation through once, blocking for each
+    /// one's duration. A looping animation plays a single cycle as well, tick
+    /// it from the main loop and `display_frame` it to keep it going.
+    pub fn play_animation<Delay>(
+        &mut self,
+        animation: &Animation,
+        delay: &mut Delay,
+    ) -> Result<(), Error<E>>
+    where
+        Delay: DelayMs<u32>,
+    {
+        for keyframe in animation.keyframes() {
+            let frame = SegmentFrame::from_cells(keyframe.cells, animation.width());
+            self.display_frame(&frame)?;
+
+            delay.delay_ms(keyframe.duration_ms);
+        }
+
+        Ok(())
+    }
+
+    /// Writes out every driver whose buffer changed since it was last written
+    fn flush(&mut self) -> Result<(), Error<E>> {
+        let mut result = Ok(());
+
+        for n in 0..self.drivers.len() {
+            let ram = driver_ram(&self.drivers[n]);
+
+            // Skip drivers already showing this content
+            if self.shadow.is_current(n, &ram) {
+                continue;
+            }
+
+            self.shadow.set(n, None);
+
+            if let Err(e) = self.write_driver(n) {
+                // When recovering, a bad driver shouldn't blank the rest of
+                // the chain
+                if self.recovery == Recovery::Disabled {
+                    return Err(e);
+                }
+
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            } else {
+                self.shadow.set(n, Some(ram));
+            }
+        }
+
+        result
+    }
+
+    /// Renders `buffer` like `Display::display`, then hands every driver's
+    /// buffer to the DMAC instead of writing them out. Returns straight
+    /// away, `DisplayDma::state` reports when the frame is out. The bus
+    /// must be shared through a `dma::DmaBus`, which holds other
+    /// transactions back until then. Recovery doesn't apply to DMA writes.
+    #[cfg(feature = "dma")]
+    pub fn display_dma(
+        &mut self,
+        dma: &mut DisplayDma,
+        buffer: &[u8],
+        enable_dot: Option<&[bool]>,
+    ) -> Result<(), Error<E>> {
+        if dma.is_busy() {
+            return Err(Error::DmaBusy);
+        }
+
+        self.render(buffer, enable_dot);
+
+        let mut frames = [[0u8; RAM_SIZE]; MAX_DRIVERS];
+
+        for (n, driver) in self.drivers.iter().enumerate() {
+            frames[n] = driver_ram(driver);
+
+            // Assume the frame makes it out, `force_refresh` if it didn't
+            self.shadow.set(n, Some(frames[n]));
+        }
+
+        dma.start(&frames[..self.drivers.len()], &self.addresses);
+
+        Ok(())
+    }
+
+    /// Writes the buffer of the driver at `index`, recovering it according
+    /// to the `Recovery` mode if the write fails.
+    fn write_driver(&mut self, index: usize) -> Result<(), Error<E>> {
+        let error = match self.drivers[index].write_display_buffer() {
+            Ok(()) => {
+                self.health[index].status = Health::Ok;
+                return Ok(());
+            }
+            Err(error) => error,
+        };
+
+        log::warn!("Driver {} write failed: {:?}", index, error);
+        self.health[index].errors = self.health[index].errors.saturating_add(1);
+
+        if let Recovery::Reinitialize { retries } = self.recovery {
+            let settings = self.settings[index];
+            let driver = &mut self.drivers[index];
+
+            for _ in 0..retries {
+                let retry =
+                    restore_driver(driver, settings).and_then(|_| driver.write_display_buffer());
+
+                match retry {
+                    Ok(()) => {
+                        log::info!("Driver {} recovered", index);
+                        self.health[index].status = Health::Recovered;
+                        return Ok(());
+                    }
+                    Err(_) => {
+                        self.health[index].errors = self.health[index].errors.saturating_add(1);
+                    }
+                }
+            }
+        }
+
+        self.health[index].status = Health::Failed;
+
+        Err(Error::Driver { index, error })
+    }
+
+    /// Fades every driver between two perceived brightness values (0 - 255),
+    /// gamma correcting each step onto the 16 hardware dimming levels.
+    pub fn fade<Delay, UXX>(
+        &mut self,
+        from: u8,
+        to: u8,
+        steps: u8,
+        delay: &mut Delay,
+        delay_ms: UXX,
+    ) -> Result<(), Error<E>>
+    where
+        Delay: DelayMs<UXX>,
+        UXX: Copy,
+    {
+        let steps = steps.max(1) as i32;
+        let mut last_level = None;
+
+        for step in 0..=steps {
+            let perceived = from as i32 + (to as i32 - from as i32) * step / steps;
+            let level = gamma_level(perceived as u8);
+
+            // Only talk to the drivers when the hardware level changes
+            if last_level != Some(level) {
+                self.set_brightness(level)?;
+                last_level = Some(level);
+            }
+
+            if step < steps {
+                delay.delay_ms(delay_ms);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps a perceived brightness (0 - 255) onto a hardware dimming level
+/// (0 - 15) using a gamma 2.0 curve.
+pub fn gamma_level(perceived: u8) -> u8 {
+    let perceived = perceived as u32;
+    let scaled = perceived * perceived * MAX_BRIGHTNESS as u32;
+
+    ((scaled + 255 * 255 / 2) / (255 * 255)) as u8
+}
+
+/// Copy of a driver's display buffer
+fn driver_ram<I2C, E>(driver: &HT16K33<I2C>) -> [u8; RAM_SIZE]
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    let mut ram = [0; RAM_SIZE];
+
+    for (byte, row) in ram.iter_mut().zip(driver.display_buffer().iter()) {
+        *byte = row.bits();
+    }
+
+    ram
+}
+
+/// Sets a driver's display buffer to `ram`
+fn load_ram<I2C, E>(driver: &mut HT16K33<I2C>, ram: &[u8; RAM_SIZE])
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    for (row, byte) in ram.iter().enumerate() {
+        for bit in 0..8u8 {
+            let location = LedLocation::new(row as u8, bit).unwrap();
+
+            driver.update_display_buffer(location, byte & (1 << bit) != 0);
+        }
     }
 }
 
+fn init_driver<I2C, E>(driver: &mut HT16K33<I2C>) -> Result<(), E>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    driver.initialize()?;
+    driver.set_display(ht16k33::Display::ON)
+}
+
+/// Re-initializes a driver that lost its state, ie. after a brown out, and
+/// re-applies its settings
+fn restore_driver<I2C, E>(driver: &mut HT16K33<I2C>, settings: DriverSettings) -> Result<(), E>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    driver.initialize()?;
+    driver.set_display(settings.blink.as_display())?;
+    driver.set_dimming(Dimming::from_bits_truncate(settings.brightness))
+}
+
 pub trait Display<E>
 where
     E: core::fmt::Debug,
 {
+    /// Displays `buffer` across the chain. Text past the end of the chain is
+    /// truncated, cells past the end of the text are cleared and missing dot
+    /// flags are treated as off. There's a dot flag per byte of `buffer`, a 7
+    /// segment colon takes one up as well.
     fn display(&mut self, buffer: &[u8], enable_dot: Option<&[bool]>) -> Result<(), E>;
 
     fn marquee<Delay, UXX>(
@@ -50,43 +782,14 @@ where
         UXX: Copy;
 }
 
-impl<I2C, E, const N: usize> Display<E> for MultiDisplay<I2C, N>
+impl<I2C, E> Display<Error<E>> for MultiDisplay<I2C>
 where
     I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
     E: core::fmt::Debug,
 {
-    fn display(&mut self, buffer: &[u8], enable_dot: Option<&[bool]>) -> Result<(), E> {
-        let drivers = self.drivers.as_mut();
-
-        for (n, buff) in buffer.chunks(LEDS_PER_DRIVER).enumerate() {
-            let driver = drivers.get_mut(n).unwrap();
-
-            for (idx, b) in buff.iter().enumerate() {
-                let index: Index = (idx as u8).into();
-
-                let ascii = if b.is_ascii() {
-                    unsafe { AsciiChar::from_ascii_unchecked(*b) }
-                } else {
-                    AsciiChar::Space
-                };
-
-                driver.update_buffer_with_char(index, ascii);
-
-                if let Some(dot_flags) = enable_dot {
-                    let flag_idx = n * 4 + idx;
-
-                    let enable = dot_flags[flag_idx];
-
-                    if enable {
-                        driver.update_buffer_with_dot(index, true);
-                    }
-                }
-            }
-
-            driver.write_display_buffer()?;
-        }
-
-        Ok(())
+    fn display(&mut self, buffer: &[u8], enable_dot: Option<&[bool]>) -> Result<(), Error<E>> {
+        self.render(buffer, enable_dot);
+        self.flush()
     }
 
     fn marquee<Delay, UXX>(
@@ -95,15 +798,14 @@ where
         delay: &mut Delay,
         delay_ms: UXX,
         clear_end: bool,
-    ) -> Result<(), E>
+    ) -> Result<(), Error<E>>
     where
         Delay: DelayMs<UXX>,
         UXX: Copy,
     {
-        let num_drivers = self.drivers.len();
-        let num_leds = num_drivers * LEDS_PER_DRIVER;
+        let num_leds = self.cells();
 
-        let mut buffer = [AsciiChar::Space as u8; MAX_DRIVERS * LEDS_PER_DRIVER];
+        let mut buffer = [b' '; MAX_DRIVERS * LEDS_PER_DRIVER];
 
         let bytes = text.as_bytes();
 
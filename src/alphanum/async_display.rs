@@ -0,0 +1,251 @@
+use super::{
+    shift_left_and_insert_last, Backpack, Blink, Error, Layout, Shadow, DISP_I2C_ADDR,
+    HT16K33_ADDRESSES, LEDS_PER_DRIVER, MAX_BRIGHTNESS, MAX_DRIVERS, OSCILLATOR_ON, RAM_SIZE,
+};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+use heapless::Vec;
+
+const DISPLAY_SETUP: u8 = 0x80;
+const DIMMING_SET: u8 = 0xE0;
+const DISPLAY_RAM: u8 = 0x00;
+
+/// Async counterpart of `MultiDisplay`, writing the HT16K33s over a single
+/// `embedded-hal-async` I2C bus so other tasks run while frames go out. Text
+/// is laid out the same way, including backpack kinds, reversed chains and
+/// rotated drivers.
+pub struct AsyncDisplay<I2C> {
+    i2c: I2C,
+    addresses: Vec<u8, MAX_DRIVERS>,
+    shadow: Shadow,
+    layout: Layout,
+}
+
+impl<I2C> AsyncDisplay<I2C>
+where
+    I2C: I2c,
+{
+    pub async fn new(i2c: I2C, addresses: &[u8]) -> Result<AsyncDisplay<I2C>, Error<I2C::Error>> {
+        if addresses.len() > MAX_DRIVERS {
+            return Err(Error::TooManyDrivers);
+        }
+
+        let mut display = AsyncDisplay {
+            i2c,
+            addresses: Vec::new(),
+            shadow: Shadow::new(),
+            layout: Layout::default(),
+        };
+
+        for address in addresses {
+            let _ = display.addresses.push(*address);
+        }
+
+        display.initialize().await?;
+
+        Ok(display)
+    }
+
+    /// Probes the eight HT16K33 addresses, starting at `DISP_I2C_ADDR`, and
+    /// chains every driver that responds in address order.
+    pub async fn scan(mut i2c: I2C) -> Result<AsyncDisplay<I2C>, Error<I2C::Error>> {
+        let mut addresses = Vec::new();
+
+        for address in DISP_I2C_ADDR..DISP_I2C_ADDR + HT16K33_ADDRESSES {
+            if i2c.write(address, &[OSCILLATOR_ON]).await.is_ok() {
+                let _ = addresses.push(address);
+            }
+        }
+
+        if addresses.is_empty() {
+            return Err(Error::NoDrivers);
+        }
+
+        let mut display = AsyncDisplay {
+            i2c,
+            addresses,
+            shadow: Shadow::new(),
+            layout: Layout::default(),
+        };
+        display.initialize().await?;
+
+        Ok(display)
+    }
+
+    async fn initialize(&mut self) -> Result<(), Error<I2C::Error>> {
+        for index in 0..self.addresses.len() {
+            self.write(index, &[OSCILLATOR_ON]).await?;
+            self.write(index, &[blink_bits(Blink::Off)]).await?;
+            self.write(index, &[DIMMING_SET | MAX_BRIGHTNESS]).await?;
+        }
+
+        log::info!("{} drivers initialized", self.addresses.len());
+
+        Ok(())
+    }
+
+    /// Number of drivers in the chain
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Number of character cells across the chain
+    pub fn cells(&self) -> usize {
+        self.layout.cells(self.addresses.len())
+    }
+
+    /// Same as `MultiDisplay::set_backpack`
+    pub fn set_backpack(&mut self, backpack: Backpack) {
+        self.layout.backpacks = [backpack; MAX_DRIVERS];
+        self.force_refresh();
+    }
+
+    /// Same as `MultiDisplay::set_backpacks`
+    pub fn set_backpacks(&mut self, backpacks: &[Backpack]) {
+        for (current, backpack) in self.layout.backpacks.iter_mut().zip(backpacks) {
+            *current = *backpack;
+        }
+
+        self.force_refresh();
+    }
+
+    /// Same as `MultiDisplay::set_driver_backpack`
+    pub fn set_driver_backpack(&mut self, index: usize, backpack: Backpack) {
+        if index < self.addresses.len() {
+            self.layout.backpacks[index] = backpack;
+            self.shadow.set(index, None);
+        }
+    }
+
+    /// Same as `MultiDisplay::set_reversed`
+    pub fn set_reversed(&mut self, reversed: bool) {
+        self.layout.reversed = reversed;
+        self.force_refresh();
+    }
+
+    /// Same as `MultiDisplay::set_rotated`
+    pub fn set_rotated(&mut self, rotated: bool) {
+        self.layout.rotated = [rotated; MAX_DRIVERS];
+        self.force_refresh();
+    }
+
+    /// Same as `MultiDisplay::set_driver_rotated`
+    pub fn set_driver_rotated(&mut self, index: usize, rotated: bool) {
+        if index < self.addresses.len() {
+            self.layout.rotated[index] = rotated;
+            self.shadow.set(index, None);
+        }
+    }
+
+    /// Makes the next update write every driver, even if its content didn't
+    /// change, ie. after a driver was power cycled.
+    pub fn force_refresh(&mut self) {
+        self.shadow.clear();
+    }
+
+    /// Sets the dimming level (0 - 15) of every driver
+    pub async fn set_brightness(&mut self, level: u8) -> Result<(), Error<I2C::Error>> {
+        let level = level.min(MAX_BRIGHTNESS);
+
+        for index in 0..self.addresses.len() {
+            self.write(index, &[DIMMING_SET | level]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the blink rate of every driver
+    pub async fn set_blink(&mut self, blink: Blink) -> Result<(), Error<I2C::Error>> {
+        for index in 0..self.addresses.len() {
+            self.write(index, &[blink_bits(blink)]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Same as `Display::display`, only writing the drivers whose content
+    /// changed
+    pub async fn display(
+        &mut self,
+        buffer: &[u8],
+        enable_dot: Option<&[bool]>,
+    ) -> Result<(), Error<I2C::Error>> {
+        let mut frames = [[0; RAM_SIZE]; MAX_DRIVERS];
+        let frames = &mut frames[..self.addresses.len()];
+
+        self.layout.render_text(frames, buffer, enable_dot);
+
+        for (n, ram) in frames.iter().enumerate() {
+            if self.shadow.is_current(n, ram) {
+                continue;
+            }
+
+            let mut bytes = [DISPLAY_RAM; RAM_SIZE + 1];
+            bytes[1..].copy_from_slice(ram);
+
+            self.shadow.set(n, None);
+            self.write(n, &bytes).await?;
+            self.shadow.set(n, Some(*ram));
+        }
+
+        Ok(())
+    }
+
+    /// Same as `Display::marquee`, awaiting `delay` between each step
+    pub async fn marquee<Delay>(
+        &mut self,
+        text: &str,
+        delay: &mut Delay,
+        delay_ms: u32,
+        clear_end: bool,
+    ) -> Result<(), Error<I2C::Error>>
+    where
+        Delay: DelayNs,
+    {
+        let num_leds = self.cells();
+
+        let mut buffer = [b' '; MAX_DRIVERS * LEDS_PER_DRIVER];
+
+        for b in text.as_bytes() {
+            shift_left_and_insert_last(*b, &mut buffer[0..num_leds]);
+
+            self.display(&buffer[0..num_leds], None).await?;
+
+            delay.delay_ms(delay_ms).await;
+        }
+
+        if clear_end {
+            for _ in 0..num_leds {
+                shift_left_and_insert_last(b' ', &mut buffer[0..num_leds]);
+
+                self.display(&buffer[0..num_leds], None).await?;
+
+                delay.delay_ms(delay_ms).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self, index: usize, bytes: &[u8]) -> Result<(), Error<I2C::Error>> {
+        self.i2c
+            .write(self.addresses[index], bytes)
+            .await
+            .map_err(|error| Error::Driver { index, error })
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+fn blink_bits(blink: Blink) -> u8 {
+    let rate = match blink {
+        Blink::Off => 0b00,
+        Blink::TwoHz => 0b01,
+        Blink::OneHz => 0b10,
+        Blink::HalfHz => 0b11,
+    };
+
+    DISPLAY_SETUP | rate << 1 | 0x01
+}
@@ -0,0 +1,266 @@
+//! Layouts of the HT16K33 backpacks `MultiDisplay` can drive. Each one knows
+//! how to turn text or 14 segment cells into its display RAM.
+
+use super::font::{self, *};
+use super::{matrix, seven_segment, LEDS_PER_DRIVER, RAM_SIZE};
+
+/// RAM address of each digit on the 7 segment backpack, the colon sits in
+/// between the second and third
+const DIGIT_ADDRESSES: [usize; 4] = [0, 2, 6, 8];
+const COLON_ADDRESS: usize = 4;
+const COLON: u8 = 0x02;
+/// Digit the colon comes before
+const COLON_DIGIT: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpack {
+    /// Quad 14 segment alphanumeric display
+    AlphaNum4,
+    /// 4 digit 7 segment display with a center colon
+    SevenSegment4,
+    /// 8x8 LED matrix, one character per matrix
+    Matrix8x8,
+}
+
+impl Default for Backpack {
+    fn default() -> Self {
+        Backpack::AlphaNum4
+    }
+}
+
+impl Backpack {
+    /// Number of character cells on the backpack
+    pub fn cells(self) -> usize {
+        match self {
+            Backpack::AlphaNum4 | Backpack::SevenSegment4 => LEDS_PER_DRIVER,
+            Backpack::Matrix8x8 => 1,
+        }
+    }
+
+    /// Renders the start of `text` into `ram`, blank padding cells the text
+    /// doesn't reach. `dots` holds a flag per byte of `text`. Returns the
+    /// number of bytes of `text` used.
+    pub(crate) fn render_text(self, ram: &mut [u8; RAM_SIZE], text: &[u8], dots: &[bool]) -> usize {
+        *ram = [0; RAM_SIZE];
+
+        let dot = |byte: usize| dots.get(byte).copied().unwrap_or(false);
+
+        match self {
+            Backpack::AlphaNum4 => {
+                for idx in 0..LEDS_PER_DRIVER {
+                    let mut segments = font::segments(text.get(idx).copied().unwrap_or(b' '));
+
+                    if dot(idx) {
+                        segments |= SEG_DP;
+                    }
+
+                    write_alphanum_cell(ram, idx, segments);
+                }
+
+                text.len().min(LEDS_PER_DRIVER)
+            }
+            Backpack::SevenSegment4 => {
+                let mut used = 0;
+
+                for (idx, address) in DIGIT_ADDRESSES.iter().enumerate() {
+                    // A ':' between the second and third digit lights the
+                    // colon instead of taking up a digit
+                    if idx == COLON_DIGIT && text.get(used) == Some(&b':') {
+                        ram[COLON_ADDRESS] |= COLON;
+                        used += 1;
+                    }
+
+                    let mut digit =
+                        seven_segment::segments(text.get(used).copied().unwrap_or(b' '));
+
+                    if used < text.len() && dot(used) {
+                        digit |= seven_segment::SEG_DP;
+                    }
+
+                    ram[*address] = digit;
+                    used = (used + 1).min(text.len());
+                }
+
+                used
+            }
+            Backpack::Matrix8x8 => {
+                matrix::draw_char(ram, text.first().copied().unwrap_or(b' '), dot(0));
+
+                text.len().min(1)
+            }
+        }
+    }
+
+    /// Turns rendered display RAM upside down, for a backpack mounted that
+    /// way. Cells are reversed and each one's segments flipped. The decimal
+    /// point ends up top left, so each dot moves over a cell to stay on the
+    /// right of its character, dropping the last cell's dot.
+    pub(crate) fn rotate(self, ram: &mut [u8; RAM_SIZE]) {
+        let rotated = *ram;
+        *ram = [0; RAM_SIZE];
+
+        match self {
+            Backpack::AlphaNum4 => {
+                for idx in 0..LEDS_PER_DRIVER {
+                    let segments = read_alphanum_cell(&rotated, idx);
+                    let to = LEDS_PER_DRIVER - 1 - idx;
+
+                    let flipped = swap_bits(segments & !SEG_DP, &ROTATED_14_SEGMENT);
+                    write_alphanum_cell(ram, to, read_alphanum_cell(ram, to) | flipped);
+
+                    if segments & SEG_DP != 0 && to > 0 {
+                        write_alphanum_cell(ram, to - 1, read_alphanum_cell(ram, to - 1) | SEG_DP);
+                    }
+                }
+            }
+            Backpack::SevenSegment4 => {
+                let digits = DIGIT_ADDRESSES.len();
+
+                for (idx, address) in DIGIT_ADDRESSES.iter().enumerate() {
+                    let digit = rotated[*address];
+                    let to = digits - 1 - idx;
+
+                    let flipped =
+                        swap_bits((digit & !seven_segment::SEG_DP) as u16, &ROTATED_7_SEGMENT);
+                    ram[DIGIT_ADDRESSES[to]] |= flipped as u8;
+
+                    if digit & seven_segment::SEG_DP != 0 && to > 0 {
+                        ram[DIGIT_ADDRESSES[to - 1]] |= seven_segment::SEG_DP;
+                    }
+                }
+
+                // The colon is symmetric
+                ram[COLON_ADDRESS] = rotated[COLON_ADDRESS];
+            }
+            Backpack::Matrix8x8 => {
+                for y in 0..matrix::HEIGHT {
+                    for x in 0..matrix::WIDTH {
+                        if matrix::pixel(&rotated, x, y) {
+                            matrix::set_pixel(
+                                ram,
+                                matrix::WIDTH - 1 - x,
+                                matrix::HEIGHT - 1 - y,
+                                true,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Renders raw 14 segment cells into `ram`, blank padding cells past the
+    /// end of `cells`
+    pub(crate) fn render_segments(self, ram: &mut [u8; RAM_SIZE], cells: &[u16]) {
+        *ram = [0; RAM_SIZE];
+
+        let cell = |idx: usize| cells.get(idx).copied().unwrap_or(0);
+
+        match self {
+            Backpack::AlphaNum4 => {
+                for idx in 0..LEDS_PER_DRIVER {
+                    write_alphanum_cell(ram, idx, cell(idx));
+                }
+            }
+            Backpack::SevenSegment4 => {
+                for (idx, address) in DIGIT_ADDRESSES.iter().enumerate() {
+                    ram[*address] = seven_segment::from_14_segment(cell(idx));
+                }
+            }
+            Backpack::Matrix8x8 => matrix::draw_segments(ram, cell(0)),
+        }
+    }
+}
+
+/// 14 segment pairs that swap places when a cell is turned upside down
+const ROTATED_14_SEGMENT: [(u16, u16); 7] = [
+    (SEG_A, SEG_D),
+    (SEG_B, SEG_E),
+    (SEG_C, SEG_F),
+    (SEG_G1, SEG_G2),
+    (SEG_H, SEG_N),
+    (SEG_J, SEG_M),
+    (SEG_K, SEG_L),
+];
+
+/// 7 segment pairs that swap places when a digit is turned upside down, G
+/// stays put
+const ROTATED_7_SEGMENT: [(u16, u16); 3] = [
+    (seven_segment::SEG_A as u16, seven_segment::SEG_D as u16),
+    (seven_segment::SEG_B as u16, seven_segment::SEG_E as u16),
+    (seven_segment::SEG_C as u16, seven_segment::SEG_F as u16),
+];
+
+fn swap_bits(bits: u16, pairs: &[(u16, u16)]) -> u16 {
+    let mut swapped = bits;
+
+    for (a, b) in pairs {
+        swapped &= !(a | b);
+
+        if bits & a != 0 {
+            swapped |= b;
+        }
+        if bits & b != 0 {
+            swapped |= a;
+        }
+    }
+
+    swapped
+}
+
+fn read_alphanum_cell(ram: &[u8; RAM_SIZE], idx: usize) -> u16 {
+    u16::from_le_bytes([ram[idx * 2], ram[idx * 2 + 1]])
+}
+
+fn write_alphanum_cell(ram: &mut [u8; RAM_SIZE], idx: usize, segments: u16) {
+    let [low, high] = segments.to_le_bytes();
+
+    ram[idx * 2] = low;
+    ram[idx * 2 + 1] = high;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digit(ram: &[u8; RAM_SIZE], idx: usize) -> u8 {
+        ram[DIGIT_ADDRESSES[idx]]
+    }
+
+    #[test]
+    fn colon_takes_a_dot_flag() {
+        let mut ram = [0; RAM_SIZE];
+        let dots = [false, true, true, false, true];
+
+        let used = Backpack::SevenSegment4.render_text(&mut ram, b"12:34", &dots);
+
+        assert_eq!(used, 5);
+        assert_eq!(ram[COLON_ADDRESS], COLON);
+        assert_eq!(digit(&ram, 0), seven_segment::segments(b'1'));
+        assert_eq!(
+            digit(&ram, 1),
+            seven_segment::segments(b'2') | seven_segment::SEG_DP
+        );
+        assert_eq!(digit(&ram, 2), seven_segment::segments(b'3'));
+        assert_eq!(
+            digit(&ram, 3),
+            seven_segment::segments(b'4') | seven_segment::SEG_DP
+        );
+    }
+
+    #[test]
+    fn short_text_pads_without_dots() {
+        let mut ram = [0; RAM_SIZE];
+
+        let used = Backpack::SevenSegment4.render_text(&mut ram, b"1", &[true, true]);
+
+        assert_eq!(used, 1);
+        assert_eq!(
+            digit(&ram, 0),
+            seven_segment::segments(b'1') | seven_segment::SEG_DP
+        );
+        assert_eq!(digit(&ram, 1), 0);
+        assert_eq!(digit(&ram, 2), 0);
+        assert_eq!(digit(&ram, 3), 0);
+    }
+}
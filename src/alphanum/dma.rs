@@ -0,0 +1,370 @@
+//! DMA backed display updates over SERCOM5 (the Metro M4 SDA / SCL pins).
+//!
+//! Every driver's buffer is queued as a frame and pushed by DMAC channel 0.
+//! Once a frame's last byte is handed over, the `DMAC_0` interrupt waits for
+//! SERCOM5 to report it sent, and the SERCOM5 interrupt addresses the next
+//! driver, so the CPU is free while frames go out. Call [`on_dmac_interrupt`]
+//! from the application's `DMAC_0` handler and [`on_sercom_interrupt`] from
+//! its `SERCOM5_0` and `SERCOM5_OTHER` handlers.
+//!
+//! SERCOM5 must already be set up as an I2C master (ie. `hal::i2c_master`).
+//! Share it wrapped in a [`DmaBus`], so other devices on the bus wait for an
+//! update to finish instead of cutting into it.
+
+use super::{MAX_DRIVERS, RAM_SIZE};
+use cortex_m::peripheral::NVIC;
+use embedded_hal::blocking::i2c;
+use metro_m4::pac::sercom0::I2CM;
+use metro_m4::pac::{interrupt, DMAC, MCLK, SERCOM5};
+
+const CHANNEL: usize = 0;
+/// Display RAM address pointer followed by the display RAM
+const FRAME_LEN: usize = RAM_SIZE + 1;
+const DISPLAY_RAM: u8 = 0x00;
+const SERCOM5_TX_TRIGGER: u8 = 0x0F;
+const CMD_STOP: u8 = 0x03;
+
+const BTCTRL_VALID: u16 = 1 << 0;
+const BTCTRL_BLOCKACT_INT: u16 = 1 << 3;
+const BTCTRL_SRCINC: u16 = 1 << 10;
+
+/// DMAC transfer descriptor, see the datasheet 22.8
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    btctrl: u16,
+    btcnt: u16,
+    srcaddr: u32,
+    dstaddr: u32,
+    descaddr: u32,
+}
+
+const EMPTY_DESCRIPTOR: Descriptor = Descriptor {
+    btctrl: 0,
+    btcnt: 0,
+    srcaddr: 0,
+    dstaddr: 0,
+    descaddr: 0,
+};
+
+static mut DESCRIPTOR: Descriptor = EMPTY_DESCRIPTOR;
+static mut WRITEBACK: Descriptor = EMPTY_DESCRIPTOR;
+static mut FRAMES: [[u8; FRAME_LEN]; MAX_DRIVERS] = [[DISPLAY_RAM; FRAME_LEN]; MAX_DRIVERS];
+static mut ADDRESSES: [u8; MAX_DRIVERS] = [0; MAX_DRIVERS];
+static mut QUEUED: usize = 0;
+static mut NEXT: usize = 0;
+static mut STATE: State = State::Idle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    Busy,
+    /// Every frame of the last update went out
+    Done,
+    /// The driver at `index` didn't ACK, the rest of the update was dropped
+    Nack {
+        index: usize,
+    },
+    /// The DMAC reported a transfer error, or the bus errored or lost
+    /// arbitration. The rest of the update was dropped.
+    Failed,
+}
+
+pub struct DisplayDma {
+    _dmac: DMAC,
+}
+
+impl DisplayDma {
+    pub fn new(dmac: DMAC, mclk: &mut MCLK, nvic: &mut NVIC) -> Self {
+        mclk.ahbmask.modify(|_, w| w.dmac_().set_bit());
+
+        unsafe {
+            dmac.baseaddr
+                .write(|w| w.baseaddr().bits(&DESCRIPTOR as *const _ as u32));
+            dmac.wrbaddr
+                .write(|w| w.wrbaddr().bits(&WRITEBACK as *const _ as u32));
+        }
+
+        dmac.ctrl.modify(|_, w| {
+            w.dmaenable()
+                .set_bit()
+                .lvlen0()
+                .set_bit()
+                .lvlen1()
+                .set_bit()
+                .lvlen2()
+                .set_bit()
+                .lvlen3()
+                .set_bit()
+        });
+
+        // One beat each time SERCOM5 is ready for the next byte
+        dmac.channel[CHANNEL].chctrla.write(|w| {
+            unsafe { w.trigsrc().bits(SERCOM5_TX_TRIGGER) }
+                .trigact()
+                .burst()
+        });
+        dmac.channel[CHANNEL]
+            .chintenset
+            .write(|w| w.tcmpl().set_bit().terr().set_bit());
+
+        unsafe {
+            nvic.set_priority(interrupt::DMAC_0, 1);
+            nvic.set_priority(interrupt::SERCOM5_0, 1);
+            nvic.set_priority(interrupt::SERCOM5_OTHER, 1);
+            NVIC::unmask(interrupt::DMAC_0);
+            NVIC::unmask(interrupt::SERCOM5_0);
+            NVIC::unmask(interrupt::SERCOM5_OTHER);
+        }
+
+        DisplayDma { _dmac: dmac }
+    }
+
+    pub fn state(&self) -> State {
+        state()
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.state() == State::Busy
+    }
+
+    /// Stops the update in progress, ie. if a driver stopped responding
+    /// mid frame, and releases the bus.
+    pub fn abort(&mut self) {
+        cortex_m::interrupt::free(|_| unsafe {
+            let i2cm = (*SERCOM5::ptr()).i2cm();
+
+            finish(i2cm, State::Idle);
+            stop(i2cm);
+        });
+    }
+
+    /// Queues one frame per driver, sent to the driver at the same index in
+    /// `addresses`, and starts pushing the first
+    pub(crate) fn start(&mut self, buffers: &[[u8; RAM_SIZE]], addresses: &[u8]) {
+        cortex_m::interrupt::free(|_| unsafe {
+            let count = buffers.len().min(addresses.len());
+
+            for (index, buffer) in buffers.iter().take(count).enumerate() {
+                FRAMES[index][0] = DISPLAY_RAM;
+                FRAMES[index][1..].copy_from_slice(buffer);
+                ADDRESSES[index] = addresses[index];
+            }
+
+            QUEUED = count;
+            NEXT = 0;
+
+            if count == 0 {
+                STATE = State::Done;
+                return;
+            }
+
+            STATE = State::Busy;
+
+            // Errors end the update whenever they happen
+            let i2cm = (*SERCOM5::ptr()).i2cm();
+            i2cm.intflag.write(|w| w.error().set_bit());
+            i2cm.intenset.write(|w| w.error().set_bit());
+
+            start_next();
+        });
+    }
+}
+
+/// The SERCOM5 I2C master, shared with the display updates. Every blocking
+/// transaction waits for the update in progress to go out first, so a
+/// `Ds3231` or `Keypad` on the same bus can't cut into a frame. Share this
+/// instead of the bare master, ie. `new_cortexm!(DmaBus<I2CMaster5<..>> =
+/// DmaBus::new(i2c))`. Not for use from interrupts that outrank the DMA
+/// ones, those would wait forever.
+pub struct DmaBus<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C> DmaBus<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        DmaBus { i2c }
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<I2C: i2c::Write> i2c::Write for DmaBus<I2C> {
+    type Error = I2C::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        wait_idle();
+        self.i2c.write(address, bytes)
+    }
+}
+
+impl<I2C: i2c::Read> i2c::Read for DmaBus<I2C> {
+    type Error = I2C::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        wait_idle();
+        self.i2c.read(address, buffer)
+    }
+}
+
+impl<I2C: i2c::WriteRead> i2c::WriteRead for DmaBus<I2C> {
+    type Error = I2C::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        wait_idle();
+        self.i2c.write_read(address, bytes, buffer)
+    }
+}
+
+fn state() -> State {
+    cortex_m::interrupt::free(|_| unsafe { STATE })
+}
+
+/// Waits for the update in progress, the interrupts move it along
+fn wait_idle() {
+    while state() == State::Busy {
+        cortex_m::asm::nop();
+    }
+}
+
+/// Call from the `DMAC_0` interrupt. The last byte of the frame is handed
+/// to SERCOM5 at this point, its MB interrupt fires once it's sent.
+pub fn on_dmac_interrupt() {
+    cortex_m::interrupt::free(|_| unsafe {
+        let channel = &(*DMAC::ptr()).channel[CHANNEL];
+        let i2cm = (*SERCOM5::ptr()).i2cm();
+
+        let flags = channel.chintflag.read();
+        channel
+            .chintflag
+            .write(|w| w.tcmpl().set_bit().terr().set_bit());
+
+        if STATE != State::Busy {
+            return;
+        }
+
+        if flags.terr().bit_is_set() {
+            finish(i2cm, State::Failed);
+            stop(i2cm);
+            return;
+        }
+
+        if flags.tcmpl().bit_is_set() {
+            i2cm.intenset.write(|w| w.mb().set_bit());
+        }
+    });
+}
+
+/// Call from the `SERCOM5_0` and `SERCOM5_OTHER` interrupts. Addresses the
+/// next driver once a frame is out, with LENEN the master has already sent
+/// the STOP, or ends the update on an error.
+pub fn on_sercom_interrupt() {
+    cortex_m::interrupt::free(|_| unsafe {
+        let i2cm = (*SERCOM5::ptr()).i2cm();
+
+        if STATE != State::Busy {
+            i2cm.intenclr.write(|w| w.mb().set_bit().error().set_bit());
+            return;
+        }
+
+        let status = i2cm.status.read();
+
+        if status.buserr().bit_is_set() || status.arblost().bit_is_set() {
+            i2cm.status
+                .write(|w| w.buserr().set_bit().arblost().set_bit());
+
+            finish(i2cm, State::Failed);
+            return;
+        }
+
+        if status.rxnack().bit_is_set() || status.lenerr().bit_is_set() {
+            i2cm.status.write(|w| w.lenerr().set_bit());
+
+            finish(i2cm, State::Nack { index: NEXT - 1 });
+            stop(i2cm);
+            return;
+        }
+
+        let flags = i2cm.intflag.read();
+
+        // Any other error, ie. a bus timeout
+        if flags.error().bit_is_set() {
+            finish(i2cm, State::Failed);
+            stop(i2cm);
+            return;
+        }
+
+        if flags.mb().bit_is_clear() {
+            return;
+        }
+
+        i2cm.intenclr.write(|w| w.mb().set_bit());
+
+        if NEXT < QUEUED {
+            // Writing ADDR clears MB
+            start_next();
+        } else {
+            i2cm.intflag.write(|w| w.mb().set_bit());
+            finish(i2cm, State::Done);
+        }
+    });
+}
+
+/// Ends the update with `state`, stopping the DMAC channel and the SERCOM5
+/// interrupts. Must run inside a critical section.
+unsafe fn finish(i2cm: &I2CM, state: State) {
+    let channel = &(*DMAC::ptr()).channel[CHANNEL];
+
+    channel.chctrla.modify(|_, w| w.enable().clear_bit());
+    i2cm.intenclr.write(|w| w.mb().set_bit().error().set_bit());
+    i2cm.intflag.write(|w| w.error().set_bit());
+
+    STATE = state;
+}
+
+/// Issues a STOP, releasing the bus
+fn stop(i2cm: &I2CM) {
+    i2cm.ctrlb.modify(|_, w| unsafe { w.cmd().bits(CMD_STOP) });
+    while i2cm.syncbusy.read().sysop().bit_is_set() {}
+}
+
+/// Points the descriptor at the next frame, enables the channel and
+/// addresses the driver. Must run inside a critical section.
+unsafe fn start_next() {
+    let channel = &(*DMAC::ptr()).channel[CHANNEL];
+    let i2cm = (*SERCOM5::ptr()).i2cm();
+
+    let frame = &FRAMES[NEXT];
+
+    // The source address of an incrementing transfer is the end of the data
+    DESCRIPTOR = Descriptor {
+        btctrl: BTCTRL_VALID | BTCTRL_BLOCKACT_INT | BTCTRL_SRCINC,
+        btcnt: FRAME_LEN as u16,
+        srcaddr: frame.as_ptr() as u32 + FRAME_LEN as u32,
+        dstaddr: &i2cm.data as *const _ as u32,
+        descaddr: 0,
+    };
+
+    channel.chctrla.modify(|_, w| w.enable().set_bit());
+
+    // With LENEN the master sends LEN bytes, then a STOP, on its own. If the
+    // previous STOP is still going out, the START waits for the bus.
+    i2cm.addr.write(|w| {
+        w.addr()
+            .bits((ADDRESSES[NEXT] as u16) << 1)
+            .lenen()
+            .set_bit()
+            .len()
+            .bits(FRAME_LEN as u8)
+    });
+    while i2cm.syncbusy.read().sysop().bit_is_set() {}
+
+    NEXT += 1;
+}
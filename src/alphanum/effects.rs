@@ -0,0 +1,449 @@
+//! Transitions between display contents. Each one is a frame generator: call
+//! `next_frame` whenever the application wants the next step (ie. on a timer
+//! tick) and send the frame to `MultiDisplay::display_frame`.
+
+use super::font::{
+    SEG_A, SEG_B, SEG_C, SEG_D, SEG_E, SEG_F, SEG_G1, SEG_G2, SEG_H, SEG_J, SEG_K, SEG_L, SEG_M,
+    SEG_N,
+};
+use super::segments::SegmentFrame;
+
+pub trait Transition {
+    /// Renders the next step into `frame`. Returns `false`, leaving `frame`
+    /// untouched, once the transition is over.
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool;
+
+    /// Runs `next` once this transition is over
+    fn then<T>(self, next: T) -> Then<Self, T>
+    where
+        Self: Sized,
+        T: Transition,
+    {
+        Then {
+            first: self,
+            second: next,
+            first_done: false,
+        }
+    }
+}
+
+pub struct Then<A, B> {
+    first: A,
+    second: B,
+    first_done: bool,
+}
+
+impl<A, B> Transition for Then<A, B>
+where
+    A: Transition,
+    B: Transition,
+{
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool {
+        if !self.first_done {
+            if self.first.next_frame(frame) {
+                return true;
+            }
+
+            self.first_done = true;
+        }
+
+        self.second.next_frame(frame)
+    }
+}
+
+/// Horizontal segments of each row, top to bottom
+const HORIZONTAL_ROWS: [u16; 3] = [SEG_A, SEG_G1 | SEG_G2, SEG_D];
+/// Vertical and diagonal segments hanging off the top and middle rows, left
+/// to right
+const VERTICAL_ROWS: [[u16; 5]; 2] = [
+    [SEG_F, SEG_H, SEG_J, SEG_K, SEG_B],
+    [SEG_E, SEG_L, SEG_M, SEG_N, SEG_C],
+];
+
+/// Moves a cell's segments `delta` rows down (negative is up). Segments
+/// moved off the cell are dropped.
+fn shift_rows(segments: u16, delta: i8) -> u16 {
+    let mut shifted = 0;
+
+    for (row, mask) in HORIZONTAL_ROWS.iter().enumerate() {
+        let to = row as i8 + delta;
+
+        if segments & mask != 0 && (0..HORIZONTAL_ROWS.len() as i8).contains(&to) {
+            shifted |= HORIZONTAL_ROWS[to as usize];
+        }
+    }
+
+    for (row, columns) in VERTICAL_ROWS.iter().enumerate() {
+        let to = row as i8 + delta;
+
+        if !(0..VERTICAL_ROWS.len() as i8).contains(&to) {
+            continue;
+        }
+
+        for (column, mask) in columns.iter().enumerate() {
+            if segments & mask != 0 {
+                shifted |= VERTICAL_ROWS[to as usize][column];
+            }
+        }
+    }
+
+    shifted
+}
+
+/// Rolls every cell that differs vertically from one text to another, like a
+/// flip clock.
+pub struct Roll {
+    from: SegmentFrame,
+    to: SegmentFrame,
+    up: bool,
+    step: u8,
+}
+
+impl Roll {
+    /// Rolls `from` out the top while `to` comes in from the bottom
+    pub fn up(from: &[u8], to: &[u8], width: usize) -> Self {
+        Roll::new(from, to, width, true)
+    }
+
+    /// Rolls `from` out the bottom while `to` comes in from the top
+    pub fn down(from: &[u8], to: &[u8], width: usize) -> Self {
+        Roll::new(from, to, width, false)
+    }
+
+    fn new(from: &[u8], to: &[u8], width: usize, up: bool) -> Self {
+        Roll {
+            from: SegmentFrame::from_text(from, width),
+            to: SegmentFrame::from_text(to, width),
+            up,
+            step: 0,
+        }
+    }
+}
+
+impl Transition for Roll {
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool {
+        let rows = HORIZONTAL_ROWS.len() as i8;
+        let step = self.step as i8;
+
+        if step > rows {
+            return false;
+        }
+
+        *frame = self.to;
+
+        for cell in 0..frame.width() {
+            let from = self.from.cell(cell);
+            let to = self.to.cell(cell);
+
+            if from == to || step == rows {
+                continue;
+            }
+
+            let segments = if self.up {
+                shift_rows(from, -step) | shift_rows(to, rows - step)
+            } else {
+                shift_rows(from, step) | shift_rows(to, step - rows)
+            };
+
+            frame.set_cell(cell, segments);
+        }
+
+        self.step += 1;
+        true
+    }
+}
+
+/// Replaces one text with another a cell at a time
+pub struct Wipe {
+    from: SegmentFrame,
+    to: SegmentFrame,
+    left_to_right: bool,
+    step: usize,
+}
+
+impl Wipe {
+    pub fn left_to_right(from: &[u8], to: &[u8], width: usize) -> Self {
+        Wipe::new(from, to, width, true)
+    }
+
+    pub fn right_to_left(from: &[u8], to: &[u8], width: usize) -> Self {
+        Wipe::new(from, to, width, false)
+    }
+
+    fn new(from: &[u8], to: &[u8], width: usize, left_to_right: bool) -> Self {
+        Wipe {
+            from: SegmentFrame::from_text(from, width),
+            to: SegmentFrame::from_text(to, width),
+            left_to_right,
+            step: 0,
+        }
+    }
+}
+
+impl Transition for Wipe {
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool {
+        let width = self.to.width();
+
+        if self.step > width {
+            return false;
+        }
+
+        *frame = self.from;
+
+        for n in 0..self.step {
+            let cell = if self.left_to_right { n } else { width - 1 - n };
+            frame.set_cell(cell, self.to.cell(cell));
+        }
+
+        self.step += 1;
+        true
+    }
+}
+
+/// Flips randomly picked segments until one text has turned into the other
+pub struct Dissolve {
+    current: SegmentFrame,
+    to: SegmentFrame,
+    per_frame: u8,
+    rng: XorShift,
+    started: bool,
+}
+
+impl Dissolve {
+    /// Flips `per_frame` segments each frame. `seed` picks the order.
+    pub fn new(from: &[u8], to: &[u8], width: usize, per_frame: u8, seed: u32) -> Self {
+        Dissolve {
+            current: SegmentFrame::from_text(from, width),
+            to: SegmentFrame::from_text(to, width),
+            per_frame: per_frame.max(1),
+            rng: XorShift::new(seed),
+            started: false,
+        }
+    }
+
+    fn remaining(&self) -> u32 {
+        self.current
+            .cells()
+            .iter()
+            .zip(self.to.cells())
+            .map(|(current, to)| (current ^ to).count_ones())
+            .sum()
+    }
+}
+
+impl Transition for Dissolve {
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool {
+        // Show the starting text first
+        if !self.started {
+            self.started = true;
+            *frame = self.current;
+            return true;
+        }
+
+        if self.remaining() == 0 {
+            return false;
+        }
+
+        for _ in 0..self.per_frame {
+            let remaining = self.remaining();
+
+            if remaining == 0 {
+                break;
+            }
+
+            // Flip the nth differing segment
+            let mut n = self.rng.next_u32() % remaining;
+
+            for cell in 0..self.current.width() {
+                let diff = self.current.cell(cell) ^ self.to.cell(cell);
+                let count = diff.count_ones();
+
+                if n >= count {
+                    n -= count;
+                    continue;
+                }
+
+                let mut bits = diff;
+                for _ in 0..n {
+                    bits &= bits - 1;
+                }
+                let bit = bits & bits.wrapping_neg();
+
+                self.current.set_cell(cell, self.current.cell(cell) ^ bit);
+                break;
+            }
+        }
+
+        *frame = self.current;
+        true
+    }
+}
+
+/// Types text out a character at a time behind a cursor, scrolling once it
+/// reaches the right edge
+pub struct Typewriter<'a> {
+    text: &'a [u8],
+    width: usize,
+    typed: usize,
+}
+
+const CURSOR: u8 = b'_';
+
+impl<'a> Typewriter<'a> {
+    pub fn new(text: &'a [u8], width: usize) -> Self {
+        Typewriter {
+            text,
+            width,
+            typed: 0,
+        }
+    }
+}
+
+impl<'a> Transition for Typewriter<'a> {
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool {
+        if self.typed > self.text.len() || self.width == 0 {
+            return false;
+        }
+
+        let typed = &self.text[..self.typed];
+        let done = self.typed == self.text.len();
+
+        // Leave room for the cursor until the last frame
+        let room = if done { self.width } else { self.width - 1 };
+        let visible = &typed[typed.len().saturating_sub(room)..];
+
+        *frame = SegmentFrame::from_text(visible, self.width);
+
+        if !done {
+            frame.set_cell(visible.len(), super::font::segments(CURSOR));
+        }
+
+        self.typed += 1;
+        true
+    }
+}
+
+/// Blinks text on and off a few times, ending with it shown
+pub struct BlinkAttention {
+    on: SegmentFrame,
+    frames: u8,
+    step: u8,
+}
+
+impl BlinkAttention {
+    pub fn new(text: &[u8], width: usize, times: u8) -> Self {
+        BlinkAttention {
+            on: SegmentFrame::from_text(text, width),
+            frames: times.saturating_mul(2),
+            step: 0,
+        }
+    }
+}
+
+impl Transition for BlinkAttention {
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool {
+        if self.step > self.frames {
+            return false;
+        }
+
+        if self.step % 2 == 0 {
+            *frame = self.on;
+        } else {
+            *frame = SegmentFrame::new(self.on.width());
+        }
+
+        self.step += 1;
+        true
+    }
+}
+
+/// Scrolls text slightly wider than the display to its end and back,
+/// pausing at each end, rather than marqueeing it off screen.
+pub struct BounceScroll<'a> {
+    text: &'a [u8],
+    width: usize,
+    pause: u8,
+    bounces: u8,
+    offset: usize,
+    forward: bool,
+    paused: u8,
+    done: bool,
+}
+
+impl<'a> BounceScroll<'a> {
+    /// Holds each end for `pause` extra frames and turns around `bounces`
+    /// times at the far end.
+    pub fn new(text: &'a [u8], width: usize, pause: u8, bounces: u8) -> Self {
+        BounceScroll {
+            text,
+            width,
+            pause,
+            // Start out "returning" so the beginning gets its pause too
+            bounces: bounces.saturating_mul(2).saturating_add(1),
+            offset: 0,
+            forward: false,
+            paused: 0,
+            done: false,
+        }
+    }
+}
+
+impl<'a> Transition for BounceScroll<'a> {
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool {
+        if self.done {
+            return false;
+        }
+
+        *frame = SegmentFrame::from_text(&self.text[self.offset..], self.width);
+
+        let max_offset = self.text.len().saturating_sub(self.width);
+        let at_end = if self.forward {
+            self.offset == max_offset
+        } else {
+            self.offset == 0
+        };
+
+        if at_end {
+            if self.paused < self.pause {
+                self.paused += 1;
+                return true;
+            }
+
+            if self.bounces == 0 || max_offset == 0 {
+                self.done = true;
+                return true;
+            }
+
+            self.bounces -= 1;
+            self.forward = !self.forward;
+            self.paused = 0;
+        }
+
+        if self.forward {
+            self.offset += 1;
+        } else {
+            self.offset -= 1;
+        }
+
+        true
+    }
+}
+
+/// Xorshift32, random enough for picking segments
+struct XorShift(u32);
+
+impl XorShift {
+    fn new(seed: u32) -> Self {
+        // Zero is a fixed point
+        XorShift(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
@@ -0,0 +1,170 @@
+//! Key scan input of the HT16K33. Up to 39 keys, wired in a 13 x 3 matrix on
+//! the ROW (K) and COM (KS) pins, are scanned by the driver itself.
+//!
+//! `Keypad` talks to the driver directly, so it needs its own handle on the
+//! bus (ie. another `shared_bus` proxy) next to the `MultiDisplay` using it.
+
+use super::Error;
+use embedded_hal::blocking::i2c;
+use embedded_hal::digital::v2::InputPin;
+use heapless::Vec;
+
+pub const MAX_KEYS: usize = 39;
+const KEY_COLUMNS: usize = 13;
+const KEY_ROWS: usize = 3;
+
+const KEY_RAM: u8 = 0x40;
+const KEY_RAM_SIZE: usize = KEY_ROWS * 2;
+const ROW_INT_SET: u8 = 0xA0;
+/// Drive the INT pin instead of ROW15
+const ROW_INT_INT: u8 = 0x01;
+const DEFAULT_DEBOUNCE: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Pressed(u8),
+    Released(u8),
+}
+
+impl KeyEvent {
+    /// Key number, `column + row * 13`
+    pub fn key(self) -> u8 {
+        match self {
+            KeyEvent::Pressed(key) | KeyEvent::Released(key) => key,
+        }
+    }
+}
+
+pub struct Keypad<I2C> {
+    i2c: I2C,
+    address: u8,
+    interrupt: bool,
+    /// Scans a change must be seen in before it's reported
+    debounce: u8,
+    /// Debounced key state, one bit per key
+    pressed: u64,
+    candidate: u64,
+    seen: u8,
+}
+
+impl<I2C, E> Keypad<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    /// Scans the keys of the driver at `address`. With `interrupt` the INT
+    /// pin is driven (active low) while keys are down, see
+    /// `poll_interrupt`.
+    pub fn new(mut i2c: I2C, address: u8, interrupt: bool) -> Result<Self, Error<E>> {
+        let row_int = if interrupt {
+            ROW_INT_SET | ROW_INT_INT
+        } else {
+            ROW_INT_SET
+        };
+
+        i2c.write(address, &[row_int]).map_err(Error::I2c)?;
+
+        Ok(Keypad {
+            i2c,
+            address,
+            interrupt,
+            debounce: DEFAULT_DEBOUNCE,
+            pressed: 0,
+            candidate: 0,
+            seen: 0,
+        })
+    }
+
+    /// Number of consecutive polls a key must hold its state for, 2 by
+    /// default. The driver scans every ~20ms, so poll at least that far
+    /// apart.
+    pub fn set_debounce(&mut self, polls: u8) {
+        self.debounce = polls.max(1);
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        (key as usize) < MAX_KEYS && self.pressed & (1 << key) != 0
+    }
+
+    /// Whether any key is down, or a change is still being debounced
+    pub fn is_active(&self) -> bool {
+        self.pressed != 0 || self.candidate != self.pressed
+    }
+
+    /// Reads the key RAM and reports keys whose debounced state changed
+    pub fn poll(&mut self) -> Result<Vec<KeyEvent, MAX_KEYS>, Error<E>> {
+        let raw = self.read_keys()?;
+
+        if raw == self.candidate {
+            self.seen = self.seen.saturating_add(1);
+        } else {
+            self.candidate = raw;
+            self.seen = 1;
+        }
+
+        let mut events = Vec::new();
+
+        if self.seen < self.debounce || self.candidate == self.pressed {
+            return Ok(events);
+        }
+
+        let changed = self.candidate ^ self.pressed;
+
+        for key in 0..MAX_KEYS as u8 {
+            if changed & (1 << key) == 0 {
+                continue;
+            }
+
+            let event = if self.candidate & (1 << key) != 0 {
+                KeyEvent::Pressed(key)
+            } else {
+                KeyEvent::Released(key)
+            };
+
+            let _ = events.push(event);
+        }
+
+        self.pressed = self.candidate;
+
+        Ok(events)
+    }
+
+    /// Polls only while the INT pin is asserted or keys are being tracked,
+    /// saving bus traffic while the keypad is idle. Requires the keypad to
+    /// be created with `interrupt`.
+    pub fn poll_interrupt<P>(&mut self, int: &P) -> Result<Vec<KeyEvent, MAX_KEYS>, Error<E>>
+    where
+        P: InputPin,
+    {
+        let asserted = int.is_low().unwrap_or(true);
+
+        if self.interrupt && !asserted && !self.is_active() {
+            return Ok(Vec::new());
+        }
+
+        self.poll()
+    }
+
+    /// Raw key state, one bit per key. Reading also clears the INT flag.
+    fn read_keys(&mut self) -> Result<u64, Error<E>> {
+        let mut ram = [0u8; KEY_RAM_SIZE];
+
+        self.i2c
+            .write_read(self.address, &[KEY_RAM], &mut ram)
+            .map_err(Error::I2c)?;
+
+        let mut keys = 0u64;
+
+        for row in 0..KEY_ROWS {
+            let columns = u16::from_le_bytes([ram[row * 2], ram[row * 2 + 1]]) as u64;
+
+            keys |= (columns & ((1 << KEY_COLUMNS) - 1)) << (row * KEY_COLUMNS);
+        }
+
+        Ok(keys)
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
@@ -0,0 +1,199 @@
+//! 8x8 LED matrix backpack, showing one 5x7 character per matrix.
+//!
+//! Glyphs are stored a column at a time, left to right, with bit 0 the top
+//! row. Column `x` of the matrix is wired to bit `(x + 7) % 8` of the byte
+//! for row `y`, same as the Adafruit library.
+
+use super::font::*;
+use super::RAM_SIZE;
+
+pub const WIDTH: usize = 8;
+pub const HEIGHT: usize = 8;
+const GLYPH_WIDTH: usize = 5;
+/// Column the glyph starts at, roughly centring it
+const GLYPH_X: usize = 1;
+
+const FIRST_PRINTABLE: u8 = b' ';
+const LAST_PRINTABLE: u8 = b'~';
+
+#[rustfmt::skip]
+const FONT: [[u8; GLYPH_WIDTH]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // )
+    [0x14, 0x08, 0x3E, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // 0
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // @
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // D
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // H
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // J
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // N
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // T
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // V
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // backslash
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // f
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // g
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // j
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // l
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // q
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // t
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // u
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // v
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // y
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x10, 0x08, 0x08, 0x10, 0x08], // ~
+];
+
+/// Pixel rows (bit `x` is column `x`) drawn for each 14 segment bit, so
+/// segment frames can be shown on a matrix
+#[rustfmt::skip]
+const SEGMENT_PIXELS: [(u16, [u8; HEIGHT]); 15] = [
+    (SEG_A,  [0x3E, 0, 0, 0, 0, 0, 0, 0]),
+    (SEG_B,  [0, 0x40, 0x40, 0, 0, 0, 0, 0]),
+    (SEG_C,  [0, 0, 0, 0, 0x40, 0x40, 0, 0]),
+    (SEG_D,  [0, 0, 0, 0, 0, 0, 0x3E, 0]),
+    (SEG_E,  [0, 0, 0, 0, 0x01, 0x01, 0, 0]),
+    (SEG_F,  [0, 0x01, 0x01, 0, 0, 0, 0, 0]),
+    (SEG_G1, [0, 0, 0, 0x0E, 0, 0, 0, 0]),
+    (SEG_G2, [0, 0, 0, 0x38, 0, 0, 0, 0]),
+    (SEG_H,  [0, 0x02, 0x04, 0, 0, 0, 0, 0]),
+    (SEG_J,  [0, 0x08, 0x08, 0, 0, 0, 0, 0]),
+    (SEG_K,  [0, 0x20, 0x10, 0, 0, 0, 0, 0]),
+    (SEG_L,  [0, 0, 0, 0, 0x04, 0x02, 0, 0]),
+    (SEG_M,  [0, 0, 0, 0, 0x08, 0x08, 0, 0]),
+    (SEG_N,  [0, 0, 0, 0, 0x10, 0x20, 0, 0]),
+    (SEG_DP, [0, 0, 0, 0, 0, 0, 0, 0x80]),
+];
+
+/// Columns of the glyph for an ASCII byte. Non printable bytes are blank.
+pub fn glyph(b: u8) -> [u8; GLYPH_WIDTH] {
+    if (FIRST_PRINTABLE..=LAST_PRINTABLE).contains(&b) {
+        FONT[(b - FIRST_PRINTABLE) as usize]
+    } else {
+        [0; GLYPH_WIDTH]
+    }
+}
+
+/// Lights or clears a pixel in display RAM, (0, 0) being the top left.
+/// Pixels off the matrix are ignored.
+pub fn set_pixel(ram: &mut [u8; RAM_SIZE], x: usize, y: usize, on: bool) {
+    if x >= WIDTH || y >= HEIGHT {
+        return;
+    }
+
+    let bit = 1 << ((x + 7) % WIDTH);
+
+    if on {
+        ram[y * 2] |= bit;
+    } else {
+        ram[y * 2] &= !bit;
+    }
+}
+
+/// Whether a pixel is lit in display RAM. Pixels off the matrix are off.
+pub fn pixel(ram: &[u8; RAM_SIZE], x: usize, y: usize) -> bool {
+    x < WIDTH && y < HEIGHT && ram[y * 2] & (1 << ((x + 7) % WIDTH)) != 0
+}
+
+/// Draws the glyph for `b`, using the bottom right pixel as its dot
+pub fn draw_char(ram: &mut [u8; RAM_SIZE], b: u8, dot: bool) {
+    for (column, bits) in glyph(b).iter().enumerate() {
+        for y in 0..HEIGHT {
+            if bits & (1 << y) != 0 {
+                set_pixel(ram, GLYPH_X + column, y, true);
+            }
+        }
+    }
+
+    if dot {
+        set_pixel(ram, WIDTH - 1, HEIGHT - 1, true);
+    }
+}
+
+/// Draws a 14 segment cell as lines on the matrix
+pub fn draw_segments(ram: &mut [u8; RAM_SIZE], segments: u16) {
+    for (mask, rows) in SEGMENT_PIXELS.iter() {
+        if segments & mask == 0 {
+            continue;
+        }
+
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..WIDTH {
+                if row & (1 << x) != 0 {
+                    set_pixel(ram, x, y, true);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use super::effects::Transition;
+use super::font::{self, *};
+use super::{LEDS_PER_DRIVER, MAX_DRIVERS};
+
+pub const MAX_CELLS: usize = MAX_DRIVERS * LEDS_PER_DRIVER;
+
+/// A single segment of a 14 segment cell, see `font` for the layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G1,
+    G2,
+    H,
+    J,
+    K,
+    L,
+    M,
+    N,
+    Dp,
+}
+
+impl Segment {
+    pub fn mask(self) -> u16 {
+        match self {
+            Segment::A => SEG_A,
+            Segment::B => SEG_B,
+            Segment::C => SEG_C,
+            Segment::D => SEG_D,
+            Segment::E => SEG_E,
+            Segment::F => SEG_F,
+            Segment::G1 => SEG_G1,
+            Segment::G2 => SEG_G2,
+            Segment::H => SEG_H,
+            Segment::J => SEG_J,
+            Segment::K => SEG_K,
+            Segment::L => SEG_L,
+            Segment::M => SEG_M,
+            Segment::N => SEG_N,
+            Segment::Dp => SEG_DP,
+        }
+    }
+}
+
+/// Raw 14 segment content of every cell on the chain, see `font` for the
+/// segment bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentFrame {
+    cells: [u16; MAX_CELLS],
+    width: usize,
+}
+
+impl SegmentFrame {
+    /// Blank frame `width` cells wide
+    pub fn new(width: usize) -> Self {
+        SegmentFrame {
+            cells: [0; MAX_CELLS],
+            width: width.min(MAX_CELLS),
+        }
+    }
+
+    /// Frame from raw cell segments, truncated or blank padded to `width`
+    pub fn from_cells(cells: &[u16], width: usize) -> Self {
+        let mut frame = SegmentFrame::new(width);
+
+        for (cell, segments) in frame.cells[..frame.width].iter_mut().zip(cells) {
+            *cell = *segments;
+        }
+
+        frame
+    }
+
+    /// Frame showing `text`, truncated or blank padded to `width`
+    pub fn from_text(text: &[u8], width: usize) -> Self {
+        let mut frame = SegmentFrame::new(width);
+
+        for (cell, b) in frame.cells[..frame.width].iter_mut().zip(text) {
+            *cell = font::segments(*b);
+        }
+
+        frame
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn cells(&self) -> &[u16] {
+        &self.cells[..self.width]
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [u16] {
+        &mut self.cells[..self.width]
+    }
+
+    /// Segments of `cell`, blank past the end of the frame
+    pub fn cell(&self, cell: usize) -> u16 {
+        self.cells().get(cell).copied().unwrap_or(0)
+    }
+
+    /// Sets the segments of `cell`. Cells past the end of the frame are
+    /// ignored.
+    pub fn set_cell(&mut self, cell: usize, segments: u16) {
+        if let Some(c) = self.cells_mut().get_mut(cell) {
+            *c = segments;
+        }
+    }
+
+    pub fn segment(&self, cell: usize, segment: Segment) -> bool {
+        self.cell(cell) & segment.mask() != 0
+    }
+
+    /// Turns a single segment on or off. Cells past the end of the frame are
+    /// ignored.
+    pub fn set_segment(&mut self, cell: usize, segment: Segment, enable: bool) {
+        if let Some(c) = self.cells_mut().get_mut(cell) {
+            if enable {
+                *c |= segment.mask();
+            } else {
+                *c &= !segment.mask();
+            }
+        }
+    }
+
+    pub fn toggle_segment(&mut self, cell: usize, segment: Segment) {
+        if let Some(c) = self.cells_mut().get_mut(cell) {
+            *c ^= segment.mask();
+        }
+    }
+
+    pub fn set_dot(&mut self, cell: usize, enable: bool) {
+        self.set_segment(cell, Segment::Dp, enable);
+    }
+
+    /// Draws a horizontal bar graph of `value` out of `max` across `cells`
+    /// cells from `start`. Each cell fills left, middle then right, giving
+    /// three steps per cell.
+    pub fn bar(&mut self, start: usize, cells: usize, value: u32, max: u32) {
+        const STEPS: [u16; 3] = [SEG_F | SEG_E, SEG_J | SEG_M, SEG_B | SEG_C];
+
+        // In u64 so large values don't overflow
+        let steps = (cells * STEPS.len()) as u64;
+        let (value, max) = (value as u64, max as u64);
+        let lit = if max == 0 {
+            0
+        } else {
+            (value.min(max) * steps + max / 2) / max
+        } as usize;
+
+        for n in 0..cells {
+            let mut segments = 0;
+
+            for (step, mask) in STEPS.iter().enumerate() {
+                if n * STEPS.len() + step < lit {
+                    segments |= mask;
+                }
+            }
+
+            self.set_cell(start + n, segments);
+        }
+    }
+
+    /// Draws a vertical level meter of `value` out of `max` in a single cell,
+    /// filling from the bottom over five steps.
+    pub fn level(&mut self, cell: usize, value: u32, max: u32) {
+        const STEPS: [u16; 5] = [SEG_D, SEG_E | SEG_C, SEG_G1 | SEG_G2, SEG_F | SEG_B, SEG_A];
+
+        let (value, max) = (value as u64, max as u64);
+        let lit = if max == 0 {
+            0
+        } else {
+            (value.min(max) * STEPS.len() as u64 + max / 2) / max
+        } as usize;
+
+        let segments = STEPS.iter().take(lit).fold(0, |acc, mask| acc | mask);
+
+        self.set_cell(cell, segments);
+    }
+
+    pub fn clear(&mut self) {
+        self.cells = [0; MAX_CELLS];
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpinnerStyle {
+    /// A segment chasing around the outside of the cell
+    Perimeter,
+    /// A line turning around the middle of the cell
+    Star,
+}
+
+/// Spinner in a single cell, ending after a number of turns. Only touches its
+/// own cell, so it can spin next to text already in the frame.
+pub struct Spinner {
+    cell: usize,
+    style: SpinnerStyle,
+    step: usize,
+    /// Steps left before it stops
+    remaining: usize,
+}
+
+const PERIMETER: [u16; 6] = [SEG_A, SEG_B, SEG_C, SEG_D, SEG_E, SEG_F];
+const STAR: [u16; 4] = [SEG_J | SEG_M, SEG_K | SEG_L, SEG_G1 | SEG_G2, SEG_H | SEG_N];
+
+impl Spinner {
+    /// Spins `turns` full turns. Driven from a main loop rather than `play`,
+    /// a large count keeps it going for as long as needed.
+    pub fn new(cell: usize, style: SpinnerStyle, turns: usize) -> Self {
+        Spinner {
+            cell,
+            style,
+            step: 0,
+            remaining: turns.saturating_mul(style.steps().len()),
+        }
+    }
+}
+
+impl SpinnerStyle {
+    fn steps(self) -> &'static [u16] {
+        match self {
+            SpinnerStyle::Perimeter => &PERIMETER,
+            SpinnerStyle::Star => &STAR,
+        }
+    }
+}
+
+impl Transition for Spinner {
+    fn next_frame(&mut self, frame: &mut SegmentFrame) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+
+        let steps = self.style.steps();
+
+        frame.set_cell(self.cell, steps[self.step % steps.len()]);
+
+        self.step = (self.step + 1) % steps.len();
+        self.remaining -= 1;
+        true
+    }
+}
+
+/// Cells to show and for how long. Borrowing the cells lets animations live
+/// in flash as `static`s.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<'a> {
+    pub cells: &'a [u16],
+    pub duration_ms: u32,
+}
+
+/// Plays a sequence of keyframes against elapsed time
+pub struct Animation<'a> {
+    keyframes: &'a [Keyframe<'a>],
+    width: usize,
+    looping: bool,
+    index: usize,
+    elapsed_ms: u32,
+    shown: bool,
+}
+
+impl<'a> Animation<'a> {
+    pub fn new(keyframes: &'a [Keyframe<'a>], width: usize, looping: bool) -> Self {
+        Animation {
+            keyframes,
+            width,
+            looping,
+            index: 0,
+            elapsed_ms: 0,
+            shown: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.keyframes.len()
+    }
+
+    pub fn keyframes(&self) -> &'a [Keyframe<'a>] {
+        self.keyframes
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn restart(&mut self) {
+        self.index = 0;
+        self.elapsed_ms = 0;
+        self.shown = false;
+    }
+
+    /// Advances the animation by `elapsed_ms`. Returns the frame to display
+    /// when it changed, `None` if the current one is still showing or the
+    /// animation is over.
+    pub fn tick(&mut self, elapsed_ms: u32) -> Option<SegmentFrame> {
+        if self.is_finished() {
+            return None;
+        }
+
+        if !self.shown {
+            self.shown = true;
+            return Some(self.current());
+        }
+
+        self.elapsed_ms = self.elapsed_ms.saturating_add(elapsed_ms);
+
+        let mut changed = false;
+
+        while !self.is_finished() && self.elapsed_ms >= self.keyframes[self.index].duration_ms {
+            self.elapsed_ms -= self.keyframes[self.index].duration_ms;
+            self.index += 1;
+            changed = true;
+
+            if self.is_finished() && self.looping {
+                self.index = 0;
+
+                // A zero length loop would never end
+                if self
+                    .keyframes
+                    .iter()
+                    .all(|keyframe| keyframe.duration_ms == 0)
+                {
+                    break;
+                }
+            }
+        }
+
+        if changed && !self.is_finished() {
+            Some(self.current())
+        } else {
+            None
+        }
+    }
+
+    fn current(&self) -> SegmentFrame {
+        SegmentFrame::from_cells(self.keyframes[self.index].cells, self.width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spinner_stops_after_its_turns() {
+        let mut frame = SegmentFrame::new(2);
+        let mut spinner = Spinner::new(1, SpinnerStyle::Star, 2);
+
+        for step in 0..2 * STAR.len() {
+            assert!(spinner.next_frame(&mut frame));
+            assert_eq!(frame.cells()[1], STAR[step % STAR.len()]);
+        }
+
+        assert!(!spinner.next_frame(&mut frame));
+        assert!(!Spinner::new(0, SpinnerStyle::Perimeter, 0).next_frame(&mut frame));
+    }
+
+    #[test]
+    fn bar_and_level_with_large_values() {
+        let mut frame = SegmentFrame::new(4);
+
+        frame.bar(0, 4, u32::MAX, u32::MAX);
+        assert!(frame
+            .cells()
+            .iter()
+            .all(|cell| *cell == SEG_F | SEG_E | SEG_J | SEG_M | SEG_B | SEG_C));
+
+        frame.bar(0, 4, u32::MAX / 2, u32::MAX);
+        assert_eq!(
+            frame.cells()[1],
+            SEG_F | SEG_E | SEG_J | SEG_M | SEG_B | SEG_C
+        );
+        assert_eq!(frame.cells()[2], 0);
+
+        frame.level(3, u32::MAX, u32::MAX);
+        assert_eq!(
+            frame.cells()[3],
+            SEG_D | SEG_E | SEG_C | SEG_G1 | SEG_G2 | SEG_F | SEG_B | SEG_A
+        );
+    }
+}
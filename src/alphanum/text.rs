@@ -0,0 +1,85 @@
+use super::segments::MAX_CELLS;
+use heapless::Vec;
+
+/// Text and dot flags for `Display::display`, built without a heap. Writing
+/// past `MAX_CELLS` is silently truncated.
+#[derive(Debug, Clone)]
+pub struct DisplayText {
+    text: Vec<u8, MAX_CELLS>,
+    dots: [bool; MAX_CELLS],
+}
+
+impl Default for DisplayText {
+    fn default() -> Self {
+        DisplayText::new()
+    }
+}
+
+impl DisplayText {
+    pub fn new() -> Self {
+        DisplayText {
+            text: Vec::new(),
+            dots: [false; MAX_CELLS],
+        }
+    }
+
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+
+    /// Dot flags of every written cell
+    pub fn dots(&self) -> &[bool] {
+        &self.dots[..self.text.len()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn push(&mut self, b: u8) {
+        let _ = self.text.push(b);
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        for b in s.bytes() {
+            self.push(b);
+        }
+    }
+
+    /// Pads with spaces up to `len` cells
+    pub fn pad_to(&mut self, len: usize) {
+        while self.text.len() < len.min(MAX_CELLS) {
+            self.push(b' ');
+        }
+    }
+
+    /// Lights the dot of `cell`, which doesn't have to be written yet
+    pub fn set_dot(&mut self, cell: usize) {
+        if let Some(dot) = self.dots.get_mut(cell) {
+            *dot = true;
+        }
+    }
+
+    /// Lights the dot of the last written cell
+    pub fn dot_last(&mut self) {
+        if let Some(last) = self.text.len().checked_sub(1) {
+            self.dots[last] = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.dots = [false; MAX_CELLS];
+    }
+}
+
+impl core::fmt::Write for DisplayText {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+//! Splits the chain into named zones, ie. the time on the first four cells
+//! and a scrolling message on the rest. Every zone has its own content and
+//! marquee, `Zones::render` composites them into one `SegmentFrame` for
+//! `MultiDisplay::display_frame`.
+
+use super::font::{self, SEG_DP};
+use super::segments::{SegmentFrame, MAX_CELLS};
+use heapless::Vec;
+
+pub const MAX_ZONES: usize = 8;
+/// Longest marquee text a zone holds, longer text is truncated
+pub const MAX_MARQUEE_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneError {
+    /// Already `MAX_ZONES` zones
+    TooManyZones,
+    /// The zone would reach past `MAX_CELLS`
+    OutOfRange,
+    /// The zone shares cells with an existing one
+    Overlap,
+    /// A zone with this name already exists
+    DuplicateName,
+    /// No zone with this name
+    UnknownZone,
+}
+
+enum Content {
+    /// Fixed segments, one per cell
+    Cells(Vec<u16, MAX_CELLS>),
+    Marquee {
+        text: Vec<u8, MAX_MARQUEE_LEN>,
+        step_ms: u32,
+        elapsed_ms: u32,
+        /// Text index shown in the last cell of the zone
+        position: usize,
+        repeat: bool,
+    },
+}
+
+struct Zone {
+    name: &'static str,
+    start: usize,
+    width: usize,
+    content: Content,
+}
+
+impl Zone {
+    /// Number of marquee steps until the text has scrolled off
+    fn marquee_steps(text: &[u8], width: usize) -> usize {
+        text.len() + width
+    }
+
+    fn render(&self, frame: &mut SegmentFrame) {
+        match &self.content {
+            Content::Cells(cells) => {
+                for n in 0..self.width {
+                    frame.set_cell(self.start + n, cells.get(n).copied().unwrap_or(0));
+                }
+            }
+            Content::Marquee { text, position, .. } => {
+                // Text comes in from the right, like `Display::marquee`
+                for n in 0..self.width {
+                    let index = (*position + n + 1).checked_sub(self.width);
+                    let b = index.and_then(|i| text.get(i)).copied().unwrap_or(b' ');
+
+                    frame.set_cell(self.start + n, font::segments(b));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Zones {
+    zones: Vec<Zone, MAX_ZONES>,
+}
+
+impl Zones {
+    pub fn new() -> Self {
+        Zones { zones: Vec::new() }
+    }
+
+    /// Adds a blank zone `width` cells wide starting at cell `start`
+    pub fn add(&mut self, name: &'static str, start: usize, width: usize) -> Result<(), ZoneError> {
+        let end = match start.checked_add(width) {
+            Some(end) if end <= MAX_CELLS => end,
+            _ => return Err(ZoneError::OutOfRange),
+        };
+
+        for zone in self.zones.iter() {
+            if zone.name == name {
+                return Err(ZoneError::DuplicateName);
+            }
+
+            if start < zone.start + zone.width && zone.start < end {
+                return Err(ZoneError::Overlap);
+            }
+        }
+
+        self.zones
+            .push(Zone {
+                name,
+                start,
+                width,
+                content: Content::Cells(Vec::new()),
+            })
+            .map_err(|_| ZoneError::TooManyZones)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), ZoneError> {
+        let index = self
+            .zones
+            .iter()
+            .position(|zone| zone.name == name)
+            .ok_or(ZoneError::UnknownZone)?;
+
+        self.zones.swap_remove(index);
+
+        Ok(())
+    }
+
+    /// Shows `text` in the zone, truncated or blank padded to its width,
+    /// stopping any marquee
+    pub fn set_text(
+        &mut self,
+        name: &str,
+        text: &[u8],
+        enable_dot: Option<&[bool]>,
+    ) -> Result<(), ZoneError> {
+        let zone = self.zone_mut(name)?;
+
+        let mut cells = Vec::new();
+
+        for n in 0..zone.width {
+            let mut segments = font::segments(text.get(n).copied().unwrap_or(b' '));
+
+            let enable = enable_dot
+                .and_then(|dot_flags| dot_flags.get(n))
+                .copied()
+                .unwrap_or(false);
+
+            if enable {
+                segments |= SEG_DP;
+            }
+
+            let _ = cells.push(segments);
+        }
+
+        zone.content = Content::Cells(cells);
+
+        Ok(())
+    }
+
+    /// Shows raw segments in the zone, stopping any marquee
+    pub fn set_cells(&mut self, name: &str, cells: &[u16]) -> Result<(), ZoneError> {
+        let zone = self.zone_mut(name)?;
+
+        let mut content = Vec::new();
+        for n in 0..zone.width {
+            let _ = content.push(cells.get(n).copied().unwrap_or(0));
+        }
+
+        zone.content = Content::Cells(content);
+
+        Ok(())
+    }
+
+    /// Scrolls `text` through the zone a cell every `step_ms`, as `tick` is
+    /// called. With `repeat` the text starts over once it's scrolled off,
+    /// otherwise the zone stays blank.
+    pub fn set_marquee(
+        &mut self,
+        name: &str,
+        text: &[u8],
+        step_ms: u32,
+        repeat: bool,
+    ) -> Result<(), ZoneError> {
+        let zone = self.zone_mut(name)?;
+
+        let mut owned = Vec::new();
+        let _ = owned.extend_from_slice(&text[..text.len().min(MAX_MARQUEE_LEN)]);
+
+        zone.content = Content::Marquee {
+            text: owned,
+            step_ms: step_ms.max(1),
+            elapsed_ms: 0,
+            position: 0,
+            repeat,
+        };
+
+        Ok(())
+    }
+
+    /// Blanks the zone
+    pub fn clear(&mut self, name: &str) -> Result<(), ZoneError> {
+        self.set_cells(name, &[])
+    }
+
+    /// Whether the zone's marquee is still scrolling
+    pub fn is_scrolling(&self, name: &str) -> Result<bool, ZoneError> {
+        let zone = self
+            .zones
+            .iter()
+            .find(|zone| zone.name == name)
+            .ok_or(ZoneError::UnknownZone)?;
+
+        Ok(match &zone.content {
+            Content::Marquee {
+                text,
+                position,
+                repeat,
+                ..
+            } => *repeat || *position + 1 < Zone::marquee_steps(text, zone.width),
+            Content::Cells(_) => false,
+        })
+    }
+
+    /// Advances every marquee by `elapsed_ms`. Returns `true` if any zone
+    /// changed and the frame should be rendered again.
+    pub fn tick(&mut self, elapsed_ms: u32) -> bool {
+        let mut changed = false;
+
+        for zone in self.zones.iter_mut() {
+            let width = zone.width;
+
+            if let Content::Marquee {
+                text,
+                step_ms,
+                elapsed_ms: zone_elapsed,
+                position,
+                repeat,
+            } = &mut zone.content
+            {
+                let steps = Zone::marquee_steps(text, width);
+
+                *zone_elapsed = zone_elapsed.saturating_add(elapsed_ms);
+
+                while *zone_elapsed >= *step_ms {
+                    *zone_elapsed -= *step_ms;
+
+                    if *position + 1 < steps {
+                        *position += 1;
+                    } else if *repeat {
+                        *position = 0;
+                    } else {
+                        *zone_elapsed = 0;
+                        break;
+                    }
+
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Draws every zone into `frame`. Cells outside any zone are left as is.
+    pub fn render(&self, frame: &mut SegmentFrame) {
+        for zone in self.zones.iter() {
+            zone.render(frame);
+        }
+    }
+
+    fn zone_mut(&mut self, name: &str) -> Result<&mut Zone, ZoneError> {
+        self.zones
+            .iter_mut()
+            .find(|zone| zone.name == name)
+            .ok_or(ZoneError::UnknownZone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_rejects_zones_past_the_end() {
+        let mut zones = Zones::new();
+
+        assert_eq!(zones.add("all", 0, MAX_CELLS), Ok(()));
+        assert_eq!(zones.add("past", MAX_CELLS, 1), Err(ZoneError::OutOfRange));
+        assert_eq!(zones.add("wide", 1, usize::MAX), Err(ZoneError::OutOfRange));
+        assert_eq!(zones.add("far", usize::MAX, 1), Err(ZoneError::OutOfRange));
+    }
+}
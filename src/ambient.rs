@@ -0,0 +1,320 @@
+use crate::alphanum::{self, MultiDisplay, MAX_BRIGHTNESS};
+use core::marker::PhantomData;
+use embedded_hal::adc::{Channel, OneShot};
+use embedded_hal::blocking::i2c;
+
+pub const VEML7700_I2C_ADDR: u8 = 0x10;
+const VEML7700_ALS_CONF: u8 = 0x00;
+const VEML7700_ALS: u8 = 0x04;
+
+/// Source of ambient light readings
+pub trait LightSensor {
+    type Error: core::fmt::Debug;
+
+    fn read_lux(&mut self) -> Result<u32, Self::Error>;
+}
+
+/// Analog light sensor (ie. ALS-PT19) read through the SAMD51 ADC. The output
+/// is assumed to be linear, reaching `full_scale_lux` at `max_reading`.
+pub struct AnalogLightSensor<A, ADC, PIN> {
+    adc: A,
+    pin: PIN,
+    full_scale_lux: u32,
+    max_reading: u32,
+    _adc: PhantomData<ADC>,
+}
+
+impl<A, ADC, PIN> AnalogLightSensor<A, ADC, PIN>
+where
+    A: OneShot<ADC, u16, PIN>,
+    PIN: Channel<ADC>,
+{
+    pub fn new(adc: A, pin: PIN, full_scale_lux: u32) -> Self {
+        AnalogLightSensor {
+            adc,
+            pin,
+            full_scale_lux,
+            // 12 bit resolution is the ADC default
+            max_reading: 4095,
+            _adc: PhantomData,
+        }
+    }
+
+    pub fn release(self) -> (A, PIN) {
+        (self.adc, self.pin)
+    }
+}
+
+impl<A, ADC, PIN> LightSensor for AnalogLightSensor<A, ADC, PIN>
+where
+    A: OneShot<ADC, u16, PIN>,
+    A::Error: core::fmt::Debug,
+    PIN: Channel<ADC>,
+{
+    type Error = A::Error;
+
+    fn read_lux(&mut self) -> Result<u32, Self::Error> {
+        let reading = nb::block!(self.adc.read(&mut self.pin))? as u32;
+
+        Ok(reading.min(self.max_reading) * self.full_scale_lux / self.max_reading)
+    }
+}
+
+/// VEML7700 lux sensor, ie. on the shared I2C bus
+pub struct Veml7700<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C, E> Veml7700<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub fn new(mut i2c: I2C) -> Result<Self, E> {
+        // Gain x1, 100ms integration time, powered on
+        i2c.write(VEML7700_I2C_ADDR, &[VEML7700_ALS_CONF, 0x00, 0x00])?;
+
+        Ok(Veml7700 { i2c })
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<I2C, E> LightSensor for Veml7700<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    type Error = E;
+
+    fn read_lux(&mut self) -> Result<u32, Self::Error> {
+        let mut buff = [0; 2];
+        self.i2c
+            .write_read(VEML7700_I2C_ADDR, &[VEML7700_ALS], &mut buff)?;
+
+        // 0.0576 lux per count at gain x1 and 100ms integration time
+        Ok(u16::from_le_bytes(buff) as u32 * 576 / 10_000)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<SE, DE> {
+    Sensor(SE),
+    Display(DE),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AutoBrightnessConfig {
+    /// Lux at or below which `min_level` is used
+    pub min_lux: u32,
+    /// Lux at or above which `max_level` is used
+    pub max_lux: u32,
+    pub min_level: u8,
+    pub max_level: u8,
+    /// Exponential smoothing factor as a power of two, 0 disables smoothing
+    pub smoothing: u8,
+    /// Extra distance, in 1/16ths of a level, the target must move past the
+    /// midpoint between two levels before switching
+    pub hysteresis: u8,
+}
+
+impl Default for AutoBrightnessConfig {
+    fn default() -> Self {
+        AutoBrightnessConfig {
+            min_lux: 1,
+            max_lux: 1000,
+            min_level: 0,
+            max_level: MAX_BRIGHTNESS,
+            smoothing: 3,
+            hysteresis: 4,
+        }
+    }
+}
+
+/// Drives `MultiDisplay` brightness from a `LightSensor`. Lux is mapped onto
+/// dimming levels logarithmically, since that's closer to how the eye
+/// perceives it.
+pub struct AutoBrightness {
+    config: AutoBrightnessConfig,
+    smoothed_lux: Option<u64>,
+    level: Option<u8>,
+}
+
+impl AutoBrightness {
+    pub fn new(config: AutoBrightnessConfig) -> Self {
+        AutoBrightness {
+            config,
+            smoothed_lux: None,
+            level: None,
+        }
+    }
+
+    /// Current dimming level, `None` until the first update
+    pub fn level(&self) -> Option<u8> {
+        self.level
+    }
+
+    /// Smoothed lux reading, `None` until the first update
+    pub fn lux(&self) -> Option<u32> {
+        self.smoothed_lux.map(|lux| (lux >> 4) as u32)
+    }
+
+    /// Takes a reading and updates the display brightness if the level
+    /// changed. Returns the level in use.
+    pub fn update<S, I2C, E>(
+        &mut self,
+        sensor: &mut S,
+        display: &mut MultiDisplay<I2C>,
+    ) -> Result<u8, Error<S::Error, alphanum::Error<E>>>
+    where
+        S: LightSensor,
+        I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+        E: core::fmt::Debug,
+    {
+        let lux = sensor.read_lux().map_err(Error::Sensor)?;
+        let target = self.target_level(self.smooth(lux));
+
+        let level = match self.level {
+            Some(current) => {
+                let current_fp = current as i32 * 16;
+                let threshold = 8 + self.config.hysteresis as i32;
+
+                if target > current_fp + threshold || target < current_fp - threshold {
+                    round_level(target)
+                } else {
+                    current
+                }
+            }
+            None => round_level(target),
+        };
+
+        if self.level != Some(level) {
+            display.set_brightness(level).map_err(Error::Display)?;
+            self.level = Some(level);
+        }
+
+        Ok(level)
+    }
+
+    /// Returns the smoothed lux with 4 fractional bits
+    fn smooth(&mut self, lux: u32) -> u64 {
+        let sample = (lux as u64) << 4;
+
+        let smoothed = match self.smoothed_lux {
+            Some(smoothed) => {
+                let shift = self.config.smoothing.min(7);
+                let diff = (sample as i64 - smoothed as i64) >> shift;
+
+                (smoothed as i64 + diff) as u64
+            }
+            None => sample,
+        };
+
+        self.smoothed_lux = Some(smoothed);
+        smoothed
+    }
+
+    /// Target dimming level in 1/16ths of a level
+    fn target_level(&self, lux_fp: u64) -> i32 {
+        let config = &self.config;
+
+        let min_lux = config.min_lux.max(1);
+        let max_lux = config.max_lux.max(min_lux.saturating_add(1));
+
+        let min = log2_fp((min_lux as u64) << 4);
+        let max = log2_fp((max_lux as u64) << 4);
+        let lux = log2_fp(lux_fp.max(1)).max(min).min(max);
+
+        let min_level = config.min_level.min(MAX_BRIGHTNESS) as i32 * 16;
+        let max_level = config.max_level.min(MAX_BRIGHTNESS) as i32 * 16;
+
+        // No range to map onto, ie. `min_lux` is `u32::MAX`
+        if max <= min {
+            return max_level;
+        }
+
+        min_level + (lux - min) * (max_level - min_level) / (max - min)
+    }
+}
+
+fn round_level(level_fp: i32) -> u8 {
+    ((level_fp + 8) / 16).max(0).min(MAX_BRIGHTNESS as i32) as u8
+}
+
+/// Binary logarithm with 4 fractional bits
+fn log2_fp(x: u64) -> i32 {
+    if x == 0 {
+        return 0;
+    }
+
+    let int = 63 - x.leading_zeros() as i32;
+
+    // Normalize to [1, 2) with 16 fractional bits
+    let mut y = if int >= 16 {
+        x >> (int - 16)
+    } else {
+        x << (16 - int)
+    };
+
+    let mut frac = 0;
+    for bit in (0..4).rev() {
+        y = (y * y) >> 16;
+
+        if y >= 2 << 16 {
+            y >>= 1;
+            frac |= 1 << bit;
+        }
+    }
+
+    int * 16 + frac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(min_lux: u32, max_lux: u32, lux: u32) -> i32 {
+        let config = AutoBrightnessConfig {
+            min_lux,
+            max_lux,
+            ..AutoBrightnessConfig::default()
+        };
+
+        AutoBrightness::new(config).target_level((lux as u64) << 4)
+    }
+
+    #[test]
+    fn target_level_spans_the_levels() {
+        let max_level = MAX_BRIGHTNESS as i32 * 16;
+
+        assert_eq!(target(1, 1000, 0), 0);
+        assert_eq!(target(1, 1000, 1), 0);
+        assert_eq!(target(1, 1000, 1000), max_level);
+        assert_eq!(target(1, 1000, u32::MAX), max_level);
+
+        let middle = target(1, 1000, 31);
+        assert!(middle > 0 && middle < max_level);
+    }
+
+    #[test]
+    fn target_level_with_an_empty_lux_range() {
+        let max_level = MAX_BRIGHTNESS as i32 * 16;
+
+        // These used to divide by zero, or overflow `min_lux + 1`
+        assert_eq!(target(0, 0, 0), 0);
+        assert_eq!(target(0, 1, 2), max_level);
+        assert_eq!(target(1000, 10, 1000), max_level);
+        assert_eq!(target(u32::MAX, 0, 0), max_level);
+        assert_eq!(target(u32::MAX, u32::MAX, u32::MAX), max_level);
+    }
+
+    #[test]
+    fn log2_fp_of_powers_of_two() {
+        assert_eq!(log2_fp(0), 0);
+        assert_eq!(log2_fp(1), 0);
+        assert_eq!(log2_fp(1 << 4), 4 * 16);
+        assert_eq!(log2_fp(u64::MAX), 63 * 16 + 15);
+    }
+}
@@ -4,6 +4,22 @@ use metro_m4::hal::gpio::{Floating, Input, Pa10, Pa11, Pa8, Pa9, Pb10, Pb11, Por
 use metro_m4::hal::qspi::{Command, OneShot, Qspi};
 use metro_m4::pac::{MCLK, QSPI};
 
+pub const SECTOR_SIZE: u32 = 0x1000;
+const PAGE_SIZE: u32 = 256;
+
+// Sectors the crate keeps its settings in. The first sector is left to the
+// application.
+pub const TIME_ZONE_SECTOR: u32 = 0x1000;
+pub const CALIBRATION_SECTOR: u32 = 0x2000;
+pub const SCHEDULE_SECTOR: u32 = 0x3000;
+pub const TIMER_SECTOR: u32 = 0x4000;
+pub const TEMPERATURE_SECTOR: u32 = 0x5000;
+
+const RECORD_MAGIC: u16 = 0x4D34;
+/// Magic, length and checksum, each a little endian `u16`
+const RECORD_HEADER_LEN: usize = 6;
+pub const MAX_RECORD_LEN: usize = SECTOR_SIZE as usize - RECORD_HEADER_LEN;
+
 pub struct QspiFlash {
     flash: Qspi<OneShot>,
 }
@@ -65,6 +81,61 @@ impl QspiFlash {
         self.flash.read_memory(addr, buffer);
     }
 
+    /// Same as `write`, but splits `buffer` at page boundaries so it can be
+    /// any length
+    pub fn write_pages(&mut self, mut addr: u32, mut buffer: &[u8]) {
+        while !buffer.is_empty() {
+            let room = (PAGE_SIZE - addr % PAGE_SIZE) as usize;
+            let (page, rest) = buffer.split_at(room.min(buffer.len()));
+
+            self.write(addr, page);
+
+            addr += page.len() as u32;
+            buffer = rest;
+        }
+    }
+
+    /// Erases the sector at `sector` and saves `data` behind a header, so
+    /// `load_record` can tell it apart from erased or half written flash.
+    /// Data past `MAX_RECORD_LEN` is dropped.
+    pub fn save_record(&mut self, sector: u32, data: &[u8]) {
+        let data = &data[..data.len().min(MAX_RECORD_LEN)];
+
+        let mut header = [0; RECORD_HEADER_LEN];
+        header[0..2].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        header[2..4].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        header[4..6].copy_from_slice(&checksum(data).to_le_bytes());
+
+        self.erase_sector(sector);
+        self.write_pages(sector + RECORD_HEADER_LEN as u32, data);
+
+        // Header last, a reset part way through leaves no valid record
+        self.write(sector, &header);
+    }
+
+    /// Reads the record saved with `save_record` into `data`, returning its
+    /// length. `None` if there's no valid record or it doesn't fit.
+    pub fn load_record(&mut self, sector: u32, data: &mut [u8]) -> Option<usize> {
+        let mut header = [0; RECORD_HEADER_LEN];
+        self.read(sector, &mut header);
+
+        let magic = u16::from_le_bytes([header[0], header[1]]);
+        let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let sum = u16::from_le_bytes([header[4], header[5]]);
+
+        if magic != RECORD_MAGIC || len > data.len() || len > MAX_RECORD_LEN {
+            return None;
+        }
+
+        self.read(sector + RECORD_HEADER_LEN as u32, &mut data[..len]);
+
+        if checksum(&data[..len]) == sum {
+            Some(len)
+        } else {
+            None
+        }
+    }
+
     pub fn erase_chip(&mut self) {
         // Chip Erase. Requires write enable. Check WIP.
         self.flash.run_command(Command::WriteEnable).unwrap();
@@ -82,6 +153,44 @@ impl QspiFlash {
     }
 }
 
+#[cfg(feature = "async")]
+impl QspiFlash {
+    /// Same as `write`, yielding to the executor while the flash is busy
+    pub async fn write_async(&mut self, addr: u32, buffer: &[u8]) {
+        self.flash.run_command(Command::WriteEnable).unwrap();
+        self.flash.write_memory(addr, buffer);
+        wait_ready_async(&mut self.flash).await;
+    }
+
+    /// Same as `erase_chip`, yielding to the executor while the flash is busy
+    pub async fn erase_chip_async(&mut self) {
+        self.flash.run_command(Command::WriteEnable).unwrap();
+        self.flash.erase_command(Command::EraseChip, 0x0).unwrap();
+        wait_ready_async(&mut self.flash).await;
+    }
+
+    /// Same as `erase_sector`, yielding to the executor while the flash is busy
+    pub async fn erase_sector_async(&mut self, addr: u32) {
+        self.flash.run_command(Command::WriteEnable).unwrap();
+        self.flash
+            .erase_command(Command::EraseSector, addr)
+            .unwrap();
+        wait_ready_async(&mut self.flash).await;
+    }
+}
+
+/// Fletcher-16 of `data`
+fn checksum(data: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+
+    for byte in data {
+        a = (a + *byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+
+    b << 8 | a
+}
+
 /// Wait for the write-in-progress and suspended write/erase.
 fn wait_ready(flash: &mut Qspi<OneShot>) {
     while flash_status(flash, Command::ReadStatus) & 0x01 != 0 {}
@@ -94,3 +203,37 @@ fn flash_status(flash: &mut Qspi<OneShot>, cmd: Command) -> u8 {
     flash.read_command(cmd, &mut out).ok().unwrap();
     out[0]
 }
+
+/// Same as `wait_ready`, yielding between each status read.
+#[cfg(feature = "async")]
+async fn wait_ready_async(flash: &mut Qspi<OneShot>) {
+    while flash_status(flash, Command::ReadStatus) & 0x01 != 0 {
+        YieldNow(false).await;
+    }
+    while flash_status(flash, Command::ReadStatus2) & 0x80 != 0 {
+        YieldNow(false).await;
+    }
+}
+
+/// Returns `Pending` once, waking itself so the executor polls again after
+/// running any other ready tasks.
+#[cfg(feature = "async")]
+struct YieldNow(bool);
+
+#[cfg(feature = "async")]
+impl core::future::Future for YieldNow {
+    type Output = ();
+
+    fn poll(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<()> {
+        if self.0 {
+            core::task::Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            core::task::Poll::Pending
+        }
+    }
+}
@@ -3,6 +3,12 @@
 #[cfg(feature = "alphanum")]
 pub mod alphanum;
 
+#[cfg(feature = "ambient")]
+pub mod ambient;
+
+#[cfg(feature = "rtc")]
+pub mod rtc;
+
 #[cfg(feature = "usb_serial")]
 pub mod usb_serial;
 
@@ -0,0 +1,382 @@
+//! Real time clock, with the formatting the clock screens need. The time
+//! comes from a DS3231 or the SAMD51's internal RTC, see `source`, and is
+//! formatted with `template`.
+
+use crate::alphanum::text::DisplayText;
+use crate::flash::{QspiFlash, TIME_ZONE_SECTOR};
+use core::fmt::Write;
+
+pub mod alarm;
+pub mod calibration;
+pub mod command;
+pub mod history;
+pub mod internal;
+pub mod schedule;
+pub mod source;
+pub mod template;
+pub mod timer;
+pub mod tz;
+
+use alarm::Alarm;
+use calibration::{Calibration, CalibrationError};
+use template::Context;
+use tz::TimeZone;
+
+pub use ds323x::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+pub use internal::InternalRtc;
+pub use source::{AnySource, Ds3231, TimeSource};
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Ends every line of a serial reply, including the CSV rows
+pub const LINE_ENDING: &str = "\r\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HourFormat {
+    H12,
+    H24,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+impl TempUnit {
+    pub fn symbol(self) -> u8 {
+        match self {
+            TempUnit::Celsius => b'C',
+            TempUnit::Fahrenheit => b'F',
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    /// The date or time given is out of the time source's range
+    InvalidDateTime,
+    /// The time source holds a date or time that doesn't exist, ie. after losing
+    /// power
+    InvalidDeviceState,
+    Calibration(CalibrationError),
+}
+
+impl<E> From<ds323x::Error<E, ()>> for Error<E> {
+    fn from(error: ds323x::Error<E, ()>) -> Self {
+        match error {
+            ds323x::Error::Comm(e) => Error::I2c(e),
+            ds323x::Error::InvalidInputData => Error::InvalidDateTime,
+            _ => Error::InvalidDeviceState,
+        }
+    }
+}
+
+pub struct Clock<S> {
+    source: S,
+    hour_format: HourFormat,
+    temp_unit: TempUnit,
+    time_zone: TimeZone,
+    /// Local alarm times, kept to re-arm them in UTC
+    alarms: [Option<Alarm>; 2],
+    calibration: Calibration,
+}
+
+impl<S: TimeSource> Clock<S> {
+    /// 12 hour time, Fahrenheit and UTC by default
+    pub fn new(source: S) -> Self {
+        Clock {
+            source,
+            hour_format: HourFormat::H12,
+            temp_unit: TempUnit::Fahrenheit,
+            time_zone: TimeZone::utc(),
+            alarms: [None; 2],
+            calibration: Calibration::default(),
+        }
+    }
+
+    pub fn set_hour_format(&mut self, hour_format: HourFormat) {
+        self.hour_format = hour_format;
+    }
+
+    pub fn hour_format(&self) -> HourFormat {
+        self.hour_format
+    }
+
+    pub fn set_temp_unit(&mut self, temp_unit: TempUnit) {
+        self.temp_unit = temp_unit;
+    }
+
+    pub fn temp_unit(&self) -> TempUnit {
+        self.temp_unit
+    }
+
+    /// Zone local time is shown in, the time source itself is kept in UTC. Call
+    /// `rearm_alarms` after if any alarms are set.
+    pub fn set_time_zone(&mut self, time_zone: TimeZone) {
+        self.time_zone = time_zone;
+    }
+
+    pub fn time_zone(&self) -> &TimeZone {
+        &self.time_zone
+    }
+
+    /// Sets the zone saved with `save_time_zone`, returning whether there was
+    /// a valid one
+    pub fn load_time_zone(&mut self, flash: &mut QspiFlash) -> bool {
+        let mut buffer = [0; tz::MAX_TZ_LEN];
+
+        let time_zone = flash
+            .load_record(TIME_ZONE_SECTOR, &mut buffer)
+            .and_then(|len| TimeZone::parse(&buffer[..len]).ok());
+
+        match time_zone {
+            Some(time_zone) => {
+                self.set_time_zone(time_zone);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn save_time_zone(&self, flash: &mut QspiFlash) {
+        flash.save_record(TIME_ZONE_SECTOR, self.time_zone.as_bytes());
+    }
+
+    /// Current UTC time
+    pub fn datetime(&mut self) -> Result<NaiveDateTime, Error<S::Error>> {
+        self.source.datetime()
+    }
+
+    /// Current time in the configured time zone
+    pub fn local_datetime(&mut self) -> Result<NaiveDateTime, Error<S::Error>> {
+        let utc = self.datetime()?;
+
+        Ok(self.time_zone.to_local(&utc))
+    }
+
+    /// Sets the current UTC time. Cancels any calibration in progress, since
+    /// its start point no longer matches the clock, so save the calibration
+    /// after if one was.
+    pub fn set_datetime(&mut self, datetime: &NaiveDateTime) -> Result<(), Error<S::Error>> {
+        self.source.set_datetime(datetime)?;
+        self.calibration.cancel();
+        self.rearm_alarms()
+    }
+
+    /// Writes the calibration's aging offset to the time source, ie. one
+    /// loaded from flash at startup
+    pub fn set_calibration(&mut self, calibration: Calibration) -> Result<(), Error<S::Error>> {
+        self.source.set_aging_offset(calibration.offset)?;
+        self.calibration = calibration;
+
+        Ok(())
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// Aging offset as read back from the time source
+    pub fn aging_offset(&mut self) -> Result<i8, Error<S::Error>> {
+        self.source.aging_offset()
+    }
+
+    /// Sets the offset by hand, dropping any calibration in progress
+    pub fn set_aging_offset(&mut self, offset: i8) -> Result<(), Error<S::Error>> {
+        self.set_calibration(Calibration::new(offset))
+    }
+
+    /// First calibration point, `host` being the reference UTC time just
+    /// received. Returns the clock's time at that point.
+    pub fn start_calibration(
+        &mut self,
+        host: &NaiveDateTime,
+    ) -> Result<NaiveDateTime, Error<S::Error>> {
+        let clock = self.datetime()?;
+        self.calibration.start(host, &clock);
+
+        Ok(clock)
+    }
+
+    /// Second calibration point. Trims the aging offset for the drift since
+    /// `start_calibration` and sets the time to `host`. Returns the drift in
+    /// ppm, positive when the clock ran fast.
+    pub fn finish_calibration(&mut self, host: &NaiveDateTime) -> Result<f32, Error<S::Error>> {
+        let clock = self.datetime()?;
+
+        let mut calibration = self.calibration;
+        let ppm = calibration.finish(host, &clock, self.source.ppm_per_step())?;
+
+        self.set_calibration(calibration)?;
+        self.set_datetime(host)?;
+
+        Ok(ppm)
+    }
+
+    /// Whether the clock stopped since the time was last set, ie. the
+    /// battery ran out, so the time can't be trusted
+    pub fn lost_power(&mut self) -> Result<bool, Error<S::Error>> {
+        self.source.lost_power()
+    }
+
+    /// Temperature in the configured unit, rounded to whole degrees. `None`
+    /// if the time source has no sensor.
+    pub fn temperature(&mut self) -> Result<Option<i16>, Error<S::Error>> {
+        let celsius = self.celsius()?;
+
+        Ok(celsius.map(|celsius| convert_temperature(celsius, self.temp_unit)))
+    }
+
+    /// Temperature in Celsius as the sensor reads it, see `temperature`
+    pub fn celsius(&mut self) -> Result<Option<f32>, Error<S::Error>> {
+        self.source.temperature()
+    }
+
+    /// What `template` needs to render the current local time. The
+    /// temperature is only read if the template shows it.
+    pub fn context(&mut self, template: &[u8]) -> Result<Context<'_>, Error<S::Error>> {
+        let utc = self.datetime()?;
+
+        let temperature = if template::uses_temperature(template) {
+            self.temperature()?
+        } else {
+            None
+        };
+
+        let zone = if template::uses_zone(template) {
+            self.time_zone.name_at(&utc)
+        } else {
+            ""
+        };
+
+        Ok(Context {
+            datetime: self.time_zone.to_local(&utc),
+            zone,
+            temperature,
+            temp_unit: self.temp_unit,
+            remaining: None,
+        })
+    }
+
+    /// Current local time formatted with `template`
+    pub fn render(&mut self, template: &[u8]) -> Result<DisplayText, Error<S::Error>> {
+        let mut text = DisplayText::new();
+        template::render(template, &self.context(template)?, &mut text);
+
+        Ok(text)
+    }
+
+    /// Current local time, see `format_time`
+    pub fn time_text(&mut self) -> Result<DisplayText, Error<S::Error>> {
+        self.render(time_template(self.hour_format))
+    }
+
+    /// Current local date and temperature, see `format_date`
+    pub fn date_text(&mut self) -> Result<DisplayText, Error<S::Error>> {
+        let context = self.context(template::DATE_WITH_TEMPERATURE)?;
+
+        let mut text = DisplayText::new();
+        template::render(date_template(context.temperature), &context, &mut text);
+
+        Ok(text)
+    }
+
+    pub fn release(self) -> S {
+        self.source
+    }
+}
+
+/// Writes `datetime` in the same format `command::parse_datetime` reads
+pub fn write_datetime<W: Write>(w: &mut W, datetime: &NaiveDateTime) -> core::fmt::Result {
+    write!(
+        w,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        datetime.year(),
+        datetime.month(),
+        datetime.day(),
+        datetime.hour(),
+        datetime.minute(),
+        datetime.second()
+    )
+}
+
+/// `celsius` in `unit`, unrounded
+pub fn to_unit(celsius: f32, unit: TempUnit) -> f32 {
+    match unit {
+        TempUnit::Celsius => celsius,
+        TempUnit::Fahrenheit => celsius * 1.8 + 32.0,
+    }
+}
+
+/// `celsius` in `unit`, rounded to whole degrees
+pub fn convert_temperature(celsius: f32, unit: TempUnit) -> i16 {
+    let degrees = to_unit(celsius, unit);
+
+    // Round half away from zero without pulling in libm
+    if degrees < 0.0 {
+        (degrees - 0.5) as i16
+    } else {
+        (degrees + 0.5) as i16
+    }
+}
+
+/// Three letter name of the weekday
+pub fn weekday_name(date: &NaiveDate) -> &'static str {
+    WEEKDAYS[date.weekday().num_days_from_monday() as usize]
+}
+
+/// `hh.mm.ss AM` in 12 hour format, `hh.mm.ss` in 24 hour format
+pub fn format_time(time: &NaiveTime, hour_format: HourFormat) -> DisplayText {
+    // Only the time fields are rendered, so any date does
+    let datetime = NaiveDate::from_ymd(2000, 1, 1).and_time(*time);
+
+    render_fixed(
+        time_template(hour_format),
+        datetime,
+        None,
+        TempUnit::Celsius,
+    )
+}
+
+/// `Mon 01.26 72F`, or `Mon 01.26` without a temperature
+pub fn format_date(date: &NaiveDate, temperature: Option<i16>, unit: TempUnit) -> DisplayText {
+    let datetime = date.and_time(NaiveTime::from_hms(0, 0, 0));
+
+    render_fixed(date_template(temperature), datetime, temperature, unit)
+}
+
+fn time_template(hour_format: HourFormat) -> &'static [u8] {
+    match hour_format {
+        HourFormat::H12 => template::TIME_12H,
+        HourFormat::H24 => template::TIME_24H,
+    }
+}
+
+fn date_template(temperature: Option<i16>) -> &'static [u8] {
+    match temperature {
+        Some(_) => template::DATE_WITH_TEMPERATURE,
+        None => template::DATE,
+    }
+}
+
+fn render_fixed(
+    template: &[u8],
+    datetime: NaiveDateTime,
+    temperature: Option<i16>,
+    temp_unit: TempUnit,
+) -> DisplayText {
+    let context = Context {
+        datetime,
+        zone: "",
+        temperature,
+        temp_unit,
+        remaining: None,
+    };
+
+    let mut text = DisplayText::new();
+    template::render(template, &context, &mut text);
+
+    text
+}
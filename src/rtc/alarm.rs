@@ -0,0 +1,173 @@
+//! Alarms. Alarms are set in local time and converted to the UTC the time
+//! source holds, then re-armed every time they go off so they follow DST
+//! changes.
+//!
+//! On the DS3231 the INT/SQW pin is open drain and pulled low on a match.
+//! Route it to an EIC channel, falling edge, and call `notify` from its
+//! interrupt handler. The internal RTC calls `notify` itself, see
+//! `internal::on_interrupt`. The main loop then calls `Clock::handle_alarms`,
+//! which reads and clears the alarm flags and runs the callback for each
+//! alarm that went off.
+
+use super::source::TimeSource;
+use super::{Clock, Datelike, Error, NaiveDateTime, NaiveTime};
+
+/// Monthly alarms can skip a month, ie. the 31st
+const MAX_SEARCH_DAYS: usize = 62;
+
+static mut PENDING: bool = false;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmSlot {
+    /// Matches to the second
+    Alarm1,
+    /// Matches to the minute on the DS3231, seconds are ignored
+    Alarm2,
+}
+
+impl AlarmSlot {
+    pub(crate) fn index(self) -> usize {
+        match self {
+            AlarmSlot::Alarm1 => 0,
+            AlarmSlot::Alarm2 => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Daily,
+    /// Days from Monday, 0 - 6
+    Weekly(u8),
+    /// Day of the month, 1 - 31. Months without that day are skipped.
+    Monthly(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alarm {
+    /// Local time
+    pub time: NaiveTime,
+    pub repeat: Repeat,
+}
+
+impl Alarm {
+    pub fn daily(time: NaiveTime) -> Self {
+        Alarm {
+            time,
+            repeat: Repeat::Daily,
+        }
+    }
+
+    pub fn weekly(weekday: u8, time: NaiveTime) -> Self {
+        Alarm {
+            time,
+            repeat: Repeat::Weekly(weekday),
+        }
+    }
+
+    pub fn monthly(day: u8, time: NaiveTime) -> Self {
+        Alarm {
+            time,
+            repeat: Repeat::Monthly(day),
+        }
+    }
+
+    /// First local time after `now` the alarm goes off, `None` if the repeat
+    /// is out of range
+    pub fn next_after(&self, now: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut date = now.date();
+
+        for _ in 0..MAX_SEARCH_DAYS {
+            let matches = match self.repeat {
+                Repeat::Daily => true,
+                Repeat::Weekly(weekday) => date.weekday().num_days_from_monday() == weekday as u32,
+                Repeat::Monthly(day) => date.day() == day as u32,
+            };
+
+            let at = date.and_time(self.time);
+
+            if matches && at > *now {
+                return Some(at);
+            }
+
+            date = date.succ_opt()?;
+        }
+
+        None
+    }
+}
+
+/// Call from the EIC interrupt handler the INT/SQW pin is routed to
+pub fn notify() {
+    cortex_m::interrupt::free(|_| unsafe { PENDING = true });
+}
+
+fn take_pending() -> bool {
+    cortex_m::interrupt::free(|_| unsafe { core::mem::replace(&mut PENDING, false) })
+}
+
+impl<S: TimeSource> Clock<S> {
+    pub fn set_alarm(&mut self, slot: AlarmSlot, alarm: Alarm) -> Result<(), Error<S::Error>> {
+        self.arm_alarm(slot, &alarm)?;
+        self.alarms[slot.index()] = Some(alarm);
+
+        Ok(())
+    }
+
+    pub fn clear_alarm(&mut self, slot: AlarmSlot) -> Result<(), Error<S::Error>> {
+        self.source.clear_alarm(slot)?;
+        self.alarms[slot.index()] = None;
+
+        Ok(())
+    }
+
+    pub fn alarm(&self, slot: AlarmSlot) -> Option<Alarm> {
+        self.alarms[slot.index()]
+    }
+
+    /// Re-arms every set alarm, needed after the time or time zone changes
+    pub fn rearm_alarms(&mut self) -> Result<(), Error<S::Error>> {
+        for slot in [AlarmSlot::Alarm1, AlarmSlot::Alarm2] {
+            if let Some(alarm) = self.alarms[slot.index()] {
+                self.arm_alarm(slot, &alarm)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Calls `on_alarm` for each alarm that went off since the last `notify`,
+    /// clearing its flag and arming it for the next time. Does nothing without
+    /// a pending `notify`, so it's cheap to call every loop.
+    pub fn handle_alarms<F>(&mut self, mut on_alarm: F) -> Result<(), Error<S::Error>>
+    where
+        F: FnMut(AlarmSlot),
+    {
+        if !take_pending() {
+            return Ok(());
+        }
+
+        for slot in [AlarmSlot::Alarm1, AlarmSlot::Alarm2] {
+            if !self.source.take_alarm(slot)? {
+                continue;
+            }
+
+            if let Some(alarm) = self.alarms[slot.index()] {
+                self.arm_alarm(slot, &alarm)?;
+            }
+
+            on_alarm(slot);
+        }
+
+        Ok(())
+    }
+
+    /// Arms `slot` with the next occurrence of `alarm`, in UTC
+    fn arm_alarm(&mut self, slot: AlarmSlot, alarm: &Alarm) -> Result<(), Error<S::Error>> {
+        let now = self.local_datetime()?;
+        let next = alarm.next_after(&now).ok_or(Error::InvalidDateTime)?;
+        let utc = self.time_zone.to_utc(&next);
+
+        self.source.set_alarm(slot, &utc, alarm.repeat)
+    }
+}
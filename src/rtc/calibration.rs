@@ -0,0 +1,232 @@
+//! Drift calibration against host time. The host sends its time twice, some
+//! days apart. The difference between how far the host and the clock moved
+//! gives the drift in ppm, which is trimmed out with the source's aging
+//! offset.
+//!
+//! Times only have whole seconds, so the interval sets the resolution: a day
+//! resolves about 12 ppm, a week under 2 ppm. The start point is saved to
+//! flash along with the offset, so the interval can span resets.
+
+use super::{Error, NaiveDateTime};
+use crate::flash::{QspiFlash, CALIBRATION_SECTOR};
+
+/// Shortest interval `finish` accepts
+pub const MIN_INTERVAL_SECS: i64 = 60 * 60;
+
+/// Offset, start flag, then the host and clock timestamps of the start
+const RECORD_LEN: usize = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// `finish` without a `start`
+    NotStarted,
+    /// Less than `MIN_INTERVAL_SECS` since `start`
+    TooShort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Calibration {
+    /// Aging offset in the time source's steps, positive slows the clock down
+    pub offset: i8,
+    /// Host and clock time, both UTC, when the calibration started
+    start: Option<(NaiveDateTime, NaiveDateTime)>,
+}
+
+impl Calibration {
+    pub fn new(offset: i8) -> Self {
+        Calibration {
+            offset,
+            start: None,
+        }
+    }
+
+    /// Starts measuring drift, `clock` being the clock's time when `host`
+    /// was received
+    pub fn start(&mut self, host: &NaiveDateTime, clock: &NaiveDateTime) {
+        self.start = Some((*host, *clock));
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.start.is_some()
+    }
+
+    /// Drops the measurement started with `start`, keeping the offset
+    pub fn cancel(&mut self) {
+        self.start = None;
+    }
+
+    /// Drift since `start`, in ppm, positive when the clock runs fast
+    pub fn drift_ppm(
+        &self,
+        host: &NaiveDateTime,
+        clock: &NaiveDateTime,
+    ) -> Result<f32, CalibrationError> {
+        let (host_start, clock_start) = self.start.ok_or(CalibrationError::NotStarted)?;
+
+        let host_elapsed = host.timestamp() - host_start.timestamp();
+        let clock_elapsed = clock.timestamp() - clock_start.timestamp();
+
+        if host_elapsed < MIN_INTERVAL_SECS {
+            return Err(CalibrationError::TooShort);
+        }
+
+        Ok(drift_ppm(host_elapsed, clock_elapsed))
+    }
+
+    /// Ends the measurement started with `start` and corrects `offset` for
+    /// the drift, returning the drift in ppm
+    pub fn finish(
+        &mut self,
+        host: &NaiveDateTime,
+        clock: &NaiveDateTime,
+        ppm_per_step: f32,
+    ) -> Result<f32, CalibrationError> {
+        let ppm = self.drift_ppm(host, clock)?;
+
+        self.offset = offset_for(self.offset, ppm, ppm_per_step);
+        self.start = None;
+
+        Ok(ppm)
+    }
+
+    /// Loads the calibration saved with `save`, `None` if there isn't a valid
+    /// one
+    pub fn load(flash: &mut QspiFlash) -> Option<Calibration> {
+        let mut record = [0; RECORD_LEN];
+
+        if flash.load_record(CALIBRATION_SECTOR, &mut record)? != RECORD_LEN {
+            return None;
+        }
+
+        let timestamp = |bytes: &[u8]| {
+            let mut timestamp = [0; 8];
+            timestamp.copy_from_slice(bytes);
+            NaiveDateTime::from_timestamp_opt(i64::from_le_bytes(timestamp), 0)
+        };
+
+        let start = if record[1] != 0 {
+            Some((timestamp(&record[2..10])?, timestamp(&record[10..18])?))
+        } else {
+            None
+        };
+
+        Some(Calibration {
+            offset: record[0] as i8,
+            start,
+        })
+    }
+
+    pub fn save(&self, flash: &mut QspiFlash) {
+        let mut record = [0; RECORD_LEN];
+
+        record[0] = self.offset as u8;
+
+        if let Some((host, clock)) = self.start {
+            record[1] = 1;
+            record[2..10].copy_from_slice(&host.timestamp().to_le_bytes());
+            record[10..18].copy_from_slice(&clock.timestamp().to_le_bytes());
+        }
+
+        flash.save_record(CALIBRATION_SECTOR, &record);
+    }
+}
+
+/// Drift in ppm of a clock that moved `clock_elapsed` seconds while the
+/// reference moved `host_elapsed`, positive when the clock runs fast
+pub fn drift_ppm(host_elapsed: i64, clock_elapsed: i64) -> f32 {
+    (clock_elapsed - host_elapsed) as f32 * 1_000_000.0 / host_elapsed as f32
+}
+
+/// New aging offset that trims out `ppm` of drift, given the `current` one
+pub fn offset_for(current: i8, ppm: f32, ppm_per_step: f32) -> i8 {
+    let steps = ppm / ppm_per_step;
+
+    // Round half away from zero without pulling in libm
+    let steps = if steps < 0.0 {
+        (steps - 0.5) as i32
+    } else {
+        (steps + 0.5) as i32
+    };
+
+    (current as i32 + steps).clamp(i8::MIN as i32, i8::MAX as i32) as i8
+}
+
+impl<E> From<CalibrationError> for Error<E> {
+    fn from(error: CalibrationError) -> Self {
+        Error::Calibration(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtc::NaiveDate;
+
+    fn datetime(day: u32, hour: u32, min: u32, sec: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2024, 1, day).and_hms(hour, min, sec)
+    }
+
+    #[test]
+    fn drift_in_ppm() {
+        assert_eq!(drift_ppm(1_000_000, 1_000_000), 0.0);
+        assert_eq!(drift_ppm(1_000_000, 1_000_002), 2.0);
+        assert_eq!(drift_ppm(1_000_000, 999_995), -5.0);
+        // A second a day
+        assert!((drift_ppm(86_400, 86_401) - 11.574).abs() < 0.001);
+    }
+
+    #[test]
+    fn offsets_round_half_away_from_zero() {
+        assert_eq!(offset_for(0, 0.0, 0.1), 0);
+        assert_eq!(offset_for(0, 1.0, 0.1), 10);
+        assert_eq!(offset_for(0, -1.0, 0.1), -10);
+        assert_eq!(offset_for(0, 1.25, 0.5), 3);
+        assert_eq!(offset_for(0, -1.25, 0.5), -3);
+        assert_eq!(offset_for(0, 1.2, 0.5), 2);
+        assert_eq!(offset_for(5, -0.2, 0.1), 3);
+    }
+
+    #[test]
+    fn offsets_clamp_to_i8() {
+        assert_eq!(offset_for(100, 10.0, 0.1), i8::MAX);
+        assert_eq!(offset_for(-100, -10.0, 0.1), i8::MIN);
+        assert_eq!(offset_for(i8::MAX, 0.0, 0.1), i8::MAX);
+    }
+
+    #[test]
+    fn finish_needs_a_long_enough_start() {
+        let mut calibration = Calibration::new(4);
+        let start = datetime(1, 0, 0, 0);
+
+        assert_eq!(
+            calibration.finish(&datetime(2, 0, 0, 0), &datetime(2, 0, 0, 0), 0.1),
+            Err(CalibrationError::NotStarted)
+        );
+
+        calibration.start(&start, &start);
+        assert_eq!(
+            calibration.drift_ppm(&datetime(1, 0, 59, 59), &datetime(1, 0, 59, 59)),
+            Err(CalibrationError::TooShort)
+        );
+
+        calibration.cancel();
+        assert!(!calibration.is_started());
+        assert_eq!(calibration.offset, 4);
+    }
+
+    #[test]
+    fn finish_trims_the_offset() {
+        let mut calibration = Calibration::new(4);
+        let start = datetime(1, 0, 0, 0);
+
+        // The clock gained a second in a day, about 11.6 ppm fast
+        calibration.start(&start, &start);
+        let ppm = calibration
+            .finish(&datetime(2, 0, 0, 0), &datetime(2, 0, 0, 1), 0.1)
+            .unwrap();
+
+        assert!((ppm - 11.574).abs() < 0.001);
+        assert_eq!(calibration.offset, 4 + 116);
+        assert!(!calibration.is_started());
+    }
+}
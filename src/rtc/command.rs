@@ -0,0 +1,978 @@
+//! Line based serial protocol for setting the clock from a host.
+//!
+//! Commands are terminated by `\n`, `\r` or NUL:
+//!
+//! ```text
+//! time set 2026-10-18T12:00:00Z   -> OK 2026-10-18T12:00:00Z
+//! time get                        -> OK 2026-10-18T12:00:05Z
+//! time set 2026-13-01T00:00:00    -> ERR invalid datetime
+//! tz set EST5EDT,M3.2.0,M11.1.0   -> OK EST5EDT,M3.2.0,M11.1.0
+//! tz get                          -> OK EST5EDT,M3.2.0,M11.1.0
+//! alarm 1 daily 07:30             -> OK
+//! alarm 2 sat 09:00:00            -> OK
+//! alarm 1 day 15 12:00            -> OK
+//! alarm 1 off                     -> OK
+//! cal start 2026-10-18T12:00:00Z  -> OK 2026-10-18T12:00:03Z
+//! cal end 2026-10-25T12:00:00Z    -> OK 4.9 ppm, offset 49
+//! cal get                         -> OK offset 49
+//! cal offset -3                   -> OK offset -3
+//! screen add time 5 %H.%M.%S      -> OK 3 screens
+//! screen add countdown 10 %D days -> OK 4 screens
+//! screen add date 5 %a %-d %b     -> OK 5 screens
+//! screen add date 5 %q            -> ERR invalid format
+//! screen get 1                    -> OK time 5 %I.%M.%S %p
+//! screen list                     -> OK time,date,time,countdown,date
+//! screen remove 3                 -> OK 4 screens
+//! screen clear | screen default   -> OK 0 screens | OK 2 screens
+//! countdown 2026-12-25T00:00:00   -> OK 66d 12:00:00
+//! countdown get                   -> OK 2026-12-25T00:00:00 66d 11:59:58
+//! countdown off                   -> OK
+//! stopwatch start | stop | reset  -> OK 0:01:23
+//! stopwatch lap                   -> OK lap 2 0:01:10
+//! stopwatch get                   -> OK 0:01:23 running, laps 0:00:40,0:01:10
+//! mode stopwatch                  -> OK stopwatch
+//! temp get                        -> OK 72.50F, today 65.75F - 75.20F
+//! temp history                    -> OK 288 samples, then CSV
+//! temp days                       -> OK 7 days, then CSV
+//! temp clear                      -> OK
+//! ```
+//!
+//! The datetime is UTC in ISO 8601, `T` or a space between the date and time
+//! and an optional trailing `Z`. The time zone is a POSIX TZ rule, see `tz`,
+//! and is saved to flash when set. Alarm times are local, see `alarm`. `cal`
+//! takes the host's current time, see `calibration`, and saves to flash as
+//! well. `screen` edits the playlist, see `schedule`, numbered from 1 with
+//! durations in seconds and the rest of the line as the format. Countdown
+//! screens show the timer's countdown. The playlist is saved to flash on every
+//! change. `countdown`, `stopwatch` and `mode` (`clock`, `countdown`,
+//! `stopwatch` or `get`) work the timer, see `timer`. It's saved to flash as
+//! well, apart from laps, and the countdown target is local time. `temp
+//! history` and `temp days` follow the reply with the CSV of `history`, one
+//! row per line. Lines are ended with `\r\n`. Lines that aren't commands are
+//! left to the application, ie. as the message screen's text.
+
+use super::alarm::{Alarm, AlarmSlot, Repeat};
+use super::history::History;
+use super::schedule::{Schedule, ScheduleError, Screen, ScreenKind};
+use super::source::TimeSource;
+use super::timer::{Mode, Timer, TimerError};
+use super::tz::TimeZone;
+use super::{
+    to_unit, write_datetime, Clock, Datelike, Error, NaiveDate, NaiveDateTime, NaiveTime, Timelike,
+    LINE_ENDING, WEEKDAYS,
+};
+use crate::flash::QspiFlash;
+use core::fmt::Write;
+use heapless::Vec;
+
+/// Years the DS3231 can hold
+const MIN_YEAR: i32 = 2000;
+const MAX_YEAR: i32 = 2099;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    SetTime(NaiveDateTime),
+    GetTime,
+    SetTimeZone(TimeZone),
+    GetTimeZone,
+    SetAlarm(AlarmSlot, Alarm),
+    ClearAlarm(AlarmSlot),
+    StartCalibration(NaiveDateTime),
+    FinishCalibration(NaiveDateTime),
+    GetCalibration,
+    SetAgingOffset(i8),
+    AddScreen(Screen),
+    /// Index from 0
+    RemoveScreen(usize),
+    GetScreen(usize),
+    ListScreens,
+    ClearScreens,
+    DefaultScreens,
+    /// Local time
+    SetCountdown(NaiveDateTime),
+    ClearCountdown,
+    GetCountdown,
+    StartStopwatch,
+    StopStopwatch,
+    Lap,
+    ResetStopwatch,
+    GetStopwatch,
+    SetMode(Mode),
+    GetMode,
+    GetTemperature,
+    DumpHistory,
+    DumpDays,
+    ClearHistory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// Not a command, ie. plain text
+    UnknownCommand,
+    /// Malformed or non existent date or time
+    InvalidDateTime,
+    /// Outside the years the DS3231 can hold
+    OutOfRange,
+    /// Not a POSIX TZ rule the parser understands
+    InvalidTimeZone,
+    /// Unknown alarm slot, repeat or time
+    InvalidAlarm,
+    /// Aging offset that isn't a number from -128 to 127
+    InvalidOffset,
+    /// Unknown screen kind or command, bad number or a format that's too long
+    InvalidScreen,
+    /// Screen format with an unknown field, see `template`
+    InvalidFormat,
+    /// Unknown `countdown`, `stopwatch` or `mode` command
+    InvalidTimer,
+    /// Unknown `temp` command
+    InvalidTemperature,
+    /// Longer than the `LineBuffer`, dropped rather than run cut short
+    LineTooLong,
+}
+
+impl ParseError {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ParseError::UnknownCommand => "unknown command",
+            ParseError::InvalidDateTime => "invalid datetime",
+            ParseError::OutOfRange => "year out of range",
+            ParseError::InvalidTimeZone => "invalid time zone",
+            ParseError::InvalidAlarm => "invalid alarm",
+            ParseError::InvalidOffset => "invalid offset",
+            ParseError::InvalidScreen => "invalid screen",
+            ParseError::InvalidFormat => "invalid format",
+            ParseError::InvalidTimer => "invalid timer",
+            ParseError::InvalidTemperature => "invalid temp",
+            ParseError::LineTooLong => "line too long",
+        }
+    }
+}
+
+impl Command {
+    pub fn parse(line: &[u8]) -> Result<Command, ParseError> {
+        let line = trim(line);
+
+        if let Some(datetime) = line.strip_prefix(b"time set ") {
+            return Ok(Command::SetTime(parse_datetime(trim(datetime))?));
+        }
+
+        if line == b"time get" {
+            return Ok(Command::GetTime);
+        }
+
+        if let Some(tz) = line.strip_prefix(b"tz set ") {
+            return TimeZone::parse(trim(tz))
+                .map(Command::SetTimeZone)
+                .map_err(|_| ParseError::InvalidTimeZone);
+        }
+
+        if line == b"tz get" {
+            return Ok(Command::GetTimeZone);
+        }
+
+        if let Some(alarm) = line.strip_prefix(b"alarm ") {
+            return parse_alarm(alarm).ok_or(ParseError::InvalidAlarm);
+        }
+
+        if let Some(datetime) = line.strip_prefix(b"cal start ") {
+            return Ok(Command::StartCalibration(parse_datetime(trim(datetime))?));
+        }
+
+        if let Some(datetime) = line.strip_prefix(b"cal end ") {
+            return Ok(Command::FinishCalibration(parse_datetime(trim(datetime))?));
+        }
+
+        if line == b"cal get" {
+            return Ok(Command::GetCalibration);
+        }
+
+        if let Some(offset) = line.strip_prefix(b"cal offset ") {
+            return parse_offset(trim(offset))
+                .map(Command::SetAgingOffset)
+                .ok_or(ParseError::InvalidOffset);
+        }
+
+        if let Some(screen) = line.strip_prefix(b"screen ") {
+            return parse_screen(screen);
+        }
+
+        if let Some(countdown) = line.strip_prefix(b"countdown ") {
+            return match trim(countdown) {
+                b"off" => Ok(Command::ClearCountdown),
+                b"get" => Ok(Command::GetCountdown),
+                datetime => Ok(Command::SetCountdown(parse_datetime(datetime)?)),
+            };
+        }
+
+        if let Some(stopwatch) = line.strip_prefix(b"stopwatch ") {
+            return match trim(stopwatch) {
+                b"start" => Ok(Command::StartStopwatch),
+                b"stop" => Ok(Command::StopStopwatch),
+                b"lap" => Ok(Command::Lap),
+                b"reset" => Ok(Command::ResetStopwatch),
+                b"get" => Ok(Command::GetStopwatch),
+                _ => Err(ParseError::InvalidTimer),
+            };
+        }
+
+        if let Some(mode) = line.strip_prefix(b"mode ") {
+            return match trim(mode) {
+                b"clock" => Ok(Command::SetMode(Mode::Clock)),
+                b"countdown" => Ok(Command::SetMode(Mode::Countdown)),
+                b"stopwatch" => Ok(Command::SetMode(Mode::Stopwatch)),
+                b"get" => Ok(Command::GetMode),
+                _ => Err(ParseError::InvalidTimer),
+            };
+        }
+
+        if let Some(temp) = line.strip_prefix(b"temp ") {
+            return match trim(temp) {
+                b"get" => Ok(Command::GetTemperature),
+                b"history" => Ok(Command::DumpHistory),
+                b"days" => Ok(Command::DumpDays),
+                b"clear" => Ok(Command::ClearHistory),
+                _ => Err(ParseError::InvalidTemperature),
+            };
+        }
+
+        Err(ParseError::UnknownCommand)
+    }
+}
+
+/// Parses `YYYY-MM-DDTHH:MM:SS`
+pub fn parse_datetime(s: &[u8]) -> Result<NaiveDateTime, ParseError> {
+    let s = s.strip_suffix(b"Z").unwrap_or(s);
+
+    if s.len() != 19
+        || s[4] != b'-'
+        || s[7] != b'-'
+        || (s[10] != b'T' && s[10] != b' ')
+        || s[13] != b':'
+        || s[16] != b':'
+    {
+        return Err(ParseError::InvalidDateTime);
+    }
+
+    let year = parse_number(&s[0..4])? as i32;
+    let month = parse_number(&s[5..7])?;
+    let day = parse_number(&s[8..10])?;
+    let hour = parse_number(&s[11..13])?;
+    let minute = parse_number(&s[14..16])?;
+    let second = parse_number(&s[17..19])?;
+
+    if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+        return Err(ParseError::OutOfRange);
+    }
+
+    NaiveDate::from_ymd_opt(year, month, day)
+        .and_then(|date| date.and_hms_opt(hour, minute, second))
+        .ok_or(ParseError::InvalidDateTime)
+}
+
+/// Parses `<1|2> <daily|mon-sun|day 1-31> HH:MM[:SS]` or `<1|2> off`
+fn parse_alarm(s: &[u8]) -> Option<Command> {
+    let mut words = s.split(|b| *b == b' ').filter(|word| !word.is_empty());
+
+    let slot = match words.next()? {
+        b"1" => AlarmSlot::Alarm1,
+        b"2" => AlarmSlot::Alarm2,
+        _ => return None,
+    };
+
+    let repeat = match words.next()? {
+        b"off" => {
+            return match words.next() {
+                None => Some(Command::ClearAlarm(slot)),
+                Some(_) => None,
+            }
+        }
+        b"daily" => Repeat::Daily,
+        b"day" => {
+            // At most two digits, so it can't overflow
+            let day = match words.next()? {
+                digits if digits.len() <= 2 => parse_number(digits).ok()?,
+                _ => return None,
+            };
+
+            if !(1..=31).contains(&day) {
+                return None;
+            }
+
+            Repeat::Monthly(day as u8)
+        }
+        name => {
+            let weekday = WEEKDAYS
+                .iter()
+                .position(|day| name.eq_ignore_ascii_case(day.as_bytes()))?;
+
+            Repeat::Weekly(weekday as u8)
+        }
+    };
+
+    let time = parse_time(words.next()?)?;
+
+    if words.next().is_some() {
+        return None;
+    }
+
+    Some(Command::SetAlarm(slot, Alarm { time, repeat }))
+}
+
+/// Parses the `screen` commands, without the `screen ` prefix
+fn parse_screen(s: &[u8]) -> Result<Command, ParseError> {
+    let (word, rest) = split_word(s);
+
+    let index = |rest: &[u8]| match parse_count(trim(rest)) {
+        Some(n) if n >= 1 => Ok(n as usize - 1),
+        _ => Err(ParseError::InvalidScreen),
+    };
+
+    match word {
+        b"add" => {}
+        b"remove" => return index(rest).map(Command::RemoveScreen),
+        b"get" => return index(rest).map(Command::GetScreen),
+        b"list" if rest.is_empty() => return Ok(Command::ListScreens),
+        b"clear" if rest.is_empty() => return Ok(Command::ClearScreens),
+        b"default" if rest.is_empty() => return Ok(Command::DefaultScreens),
+        _ => return Err(ParseError::InvalidScreen),
+    }
+
+    let (kind, rest) = split_word(rest);
+    let (seconds, format) = split_word(rest);
+
+    let kind = match kind {
+        b"time" => ScreenKind::Time,
+        b"date" => ScreenKind::Date,
+        b"temp" => ScreenKind::Temperature,
+        b"message" => ScreenKind::Message,
+        b"countdown" => ScreenKind::Countdown,
+        _ => return Err(ParseError::InvalidScreen),
+    };
+
+    let duration_ms = match parse_count(seconds) {
+        Some(seconds) if seconds >= 1 => seconds * 1000,
+        _ => return Err(ParseError::InvalidScreen),
+    };
+
+    Screen::new(kind, duration_ms, format)
+        .map(Command::AddScreen)
+        .map_err(|e| match e {
+            ScheduleError::Format(_) => ParseError::InvalidFormat,
+            _ => ParseError::InvalidScreen,
+        })
+}
+
+/// Up to five digits, so it can't overflow
+fn parse_count(digits: &[u8]) -> Option<u32> {
+    if digits.is_empty() || digits.len() > 5 {
+        return None;
+    }
+
+    parse_number(digits).ok()
+}
+
+/// First space separated word of `s` and the rest, without the spaces in
+/// between
+fn split_word(s: &[u8]) -> (&[u8], &[u8]) {
+    let s = trim(s);
+    let end = s.iter().position(|b| *b == b' ').unwrap_or(s.len());
+
+    (&s[..end], trim(&s[end..]))
+}
+
+/// Parses `-128` to `127`
+fn parse_offset(s: &[u8]) -> Option<i8> {
+    let (negative, digits) = match s.strip_prefix(b"-") {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+
+    if digits.is_empty() || digits.len() > 3 {
+        return None;
+    }
+
+    let value = parse_number(digits).ok()? as i16;
+    let value = if negative { -value } else { value };
+
+    if (i8::MIN as i16..=i8::MAX as i16).contains(&value) {
+        Some(value as i8)
+    } else {
+        None
+    }
+}
+
+/// Parses `HH:MM` or `HH:MM:SS`
+fn parse_time(s: &[u8]) -> Option<NaiveTime> {
+    if (s.len() != 5 && s.len() != 8) || s[2] != b':' || (s.len() == 8 && s[5] != b':') {
+        return None;
+    }
+
+    let hour = parse_number(&s[0..2]).ok()?;
+    let minute = parse_number(&s[3..5]).ok()?;
+    let second = match s.get(6..8) {
+        Some(second) => parse_number(second).ok()?,
+        None => 0,
+    };
+
+    NaiveTime::from_hms_opt(hour, minute, second)
+}
+
+/// Runs `command` against `clock` and writes the reply into `reply`, without
+/// a `LINE_ENDING` after its last line. That's the only line apart from
+/// `temp history` and `temp days`, which follow it with CSV rows. A new time
+/// zone, calibration, playlist or timer state is saved to `flash`, as is the
+/// temperature history once cleared.
+pub fn execute<S, W>(
+    clock: &mut Clock<S>,
+    schedule: &mut Schedule,
+    timer: &mut Timer,
+    history: &mut History,
+    flash: &mut QspiFlash,
+    command: &Command,
+    reply: &mut W,
+) where
+    S: TimeSource,
+    W: Write,
+{
+    let result = match command {
+        Command::SetTime(datetime) => {
+            let calibrating = clock.calibration().is_started();
+            let result = clock.set_datetime(datetime);
+
+            // Setting the time cancels the calibration in progress
+            if calibrating && !clock.calibration().is_started() {
+                clock.calibration().save(flash);
+            }
+
+            result.and_then(|_| clock.datetime())
+        }
+        Command::GetTime => clock.datetime(),
+        Command::SetTimeZone(time_zone) => {
+            clock.set_time_zone(time_zone.clone());
+            clock.save_time_zone(flash);
+
+            // Alarms are in local time
+            return match clock.rearm_alarms() {
+                Ok(()) => write_time_zone(reply, clock.time_zone()),
+                Err(e) => write_status(reply, Err(e)),
+            };
+        }
+        Command::GetTimeZone => return write_time_zone(reply, clock.time_zone()),
+        Command::SetAlarm(slot, alarm) => {
+            return write_status(reply, clock.set_alarm(*slot, *alarm))
+        }
+        Command::ClearAlarm(slot) => return write_status(reply, clock.clear_alarm(*slot)),
+        Command::StartCalibration(host) => {
+            let result = clock.start_calibration(host);
+
+            if result.is_ok() {
+                clock.calibration().save(flash);
+            }
+
+            result
+        }
+        Command::FinishCalibration(host) => {
+            let result = clock.finish_calibration(host);
+
+            if result.is_ok() {
+                clock.calibration().save(flash);
+            }
+
+            let _ = match result {
+                Ok(ppm) => write!(
+                    reply,
+                    "OK {:.1} ppm, offset {}",
+                    ppm,
+                    clock.calibration().offset
+                ),
+                Err(e) => write!(reply, "ERR {:?}", e),
+            };
+
+            return;
+        }
+        Command::GetCalibration => {
+            let _ = match clock.aging_offset() {
+                Ok(offset) if clock.calibration().is_started() => {
+                    write!(reply, "OK offset {}, calibrating", offset)
+                }
+                Ok(offset) => write!(reply, "OK offset {}", offset),
+                Err(e) => write!(reply, "ERR {:?}", e),
+            };
+
+            return;
+        }
+        Command::SetAgingOffset(offset) => {
+            let result = clock.set_aging_offset(*offset);
+
+            if result.is_ok() {
+                clock.calibration().save(flash);
+            }
+
+            let _ = match result {
+                Ok(()) => write!(reply, "OK offset {}", offset),
+                Err(e) => write!(reply, "ERR {:?}", e),
+            };
+
+            return;
+        }
+        Command::AddScreen(_)
+        | Command::RemoveScreen(_)
+        | Command::GetScreen(_)
+        | Command::ListScreens
+        | Command::ClearScreens
+        | Command::DefaultScreens => return execute_screen(schedule, flash, command, reply),
+        Command::SetCountdown(_)
+        | Command::ClearCountdown
+        | Command::GetCountdown
+        | Command::StartStopwatch
+        | Command::StopStopwatch
+        | Command::Lap
+        | Command::ResetStopwatch
+        | Command::GetStopwatch
+        | Command::SetMode(_)
+        | Command::GetMode => return execute_timer(clock, timer, flash, command, reply),
+        Command::GetTemperature => {
+            let _ = match clock.celsius() {
+                Ok(Some(celsius)) => write_temperature(reply, clock, history, celsius),
+                Ok(None) => reply.write_str("ERR no sensor"),
+                Err(e) => write!(reply, "ERR {:?}", e),
+            };
+
+            return;
+        }
+        Command::DumpHistory => {
+            let _ = write!(
+                reply,
+                "OK {} samples{}",
+                history.samples().len(),
+                LINE_ENDING
+            );
+            let _ = history.write_csv(reply, clock.temp_unit());
+
+            return;
+        }
+        Command::DumpDays => {
+            let _ = write!(reply, "OK {} days{}", history.days().len(), LINE_ENDING);
+            let _ = history.write_days_csv(reply, clock.temp_unit());
+
+            return;
+        }
+        Command::ClearHistory => {
+            history.clear();
+            history.save(flash);
+
+            let _ = reply.write_str("OK");
+
+            return;
+        }
+    };
+
+    let _ = match result {
+        Ok(datetime) => {
+            let _ = reply.write_str("OK ");
+            let _ = write_datetime(reply, &datetime);
+            reply.write_char('Z')
+        }
+        Err(e) => write!(reply, "ERR {:?}", e),
+    };
+}
+
+fn execute_screen<W: Write>(
+    schedule: &mut Schedule,
+    flash: &mut QspiFlash,
+    command: &Command,
+    reply: &mut W,
+) {
+    let result = match command {
+        Command::AddScreen(screen) => schedule.add(screen.clone()),
+        Command::RemoveScreen(index) => schedule.remove(*index).map(|_| ()),
+        Command::ClearScreens => {
+            schedule.clear();
+            Ok(())
+        }
+        Command::DefaultScreens => {
+            *schedule = Schedule::default_playlist();
+            Ok(())
+        }
+        Command::GetScreen(index) => {
+            let _ = match schedule.screens().get(*index) {
+                Some(screen) => write_screen(reply, screen),
+                None => write!(reply, "ERR {:?}", ScheduleError::OutOfRange),
+            };
+
+            return;
+        }
+        Command::ListScreens => {
+            let _ = reply.write_str("OK ");
+
+            for (i, screen) in schedule.screens().iter().enumerate() {
+                if i > 0 {
+                    let _ = reply.write_char(',');
+                }
+
+                let _ = reply.write_str(screen.kind.name());
+            }
+
+            return;
+        }
+        _ => return,
+    };
+
+    let _ = match result {
+        Ok(()) => {
+            schedule.save(flash);
+            write!(reply, "OK {} screens", schedule.screens().len())
+        }
+        Err(e) => write!(reply, "ERR {:?}", e),
+    };
+}
+
+fn execute_timer<S, W>(
+    clock: &mut Clock<S>,
+    timer: &mut Timer,
+    flash: &mut QspiFlash,
+    command: &Command,
+    reply: &mut W,
+) where
+    S: TimeSource,
+    W: Write,
+{
+    let now = match clock.datetime() {
+        Ok(now) => now,
+        Err(e) => {
+            let _ = write!(reply, "ERR {:?}", e);
+            return;
+        }
+    };
+
+    let stopwatch = &mut timer.stopwatch;
+
+    let result = match command {
+        Command::SetCountdown(local) => {
+            timer.countdown.set(&clock.time_zone().to_utc(local));
+            Ok(())
+        }
+        Command::ClearCountdown => {
+            timer.countdown.clear();
+            let _ = reply.write_str("OK");
+            timer.save(flash);
+
+            return;
+        }
+        Command::GetCountdown => {
+            let _ = match timer.countdown.target() {
+                Some(target) => write_countdown(reply, clock, &target, &now),
+                None => write!(reply, "ERR {:?}", TimerError::NoCountdown),
+            };
+
+            return;
+        }
+        Command::StartStopwatch => stopwatch.start(&now),
+        Command::StopStopwatch => stopwatch.stop(&now),
+        Command::ResetStopwatch => {
+            stopwatch.reset();
+            Ok(())
+        }
+        Command::Lap => {
+            // Laps aren't saved, so no need to touch flash
+            let _ = match stopwatch.lap(&now) {
+                Ok(lap) => {
+                    let _ = write!(reply, "OK lap {} ", stopwatch.laps().len());
+                    write_duration(reply, lap)
+                }
+                Err(e) => write!(reply, "ERR {:?}", e),
+            };
+
+            return;
+        }
+        Command::GetStopwatch => {
+            let _ = write_stopwatch(reply, timer, &now);
+            return;
+        }
+        Command::SetMode(mode) => {
+            timer.mode = *mode;
+            Ok(())
+        }
+        Command::GetMode => {
+            let _ = write!(reply, "OK {}", timer.mode.name());
+            return;
+        }
+        _ => return,
+    };
+
+    if let Err(e) = result {
+        let _ = write!(reply, "ERR {:?}", e);
+        return;
+    }
+
+    timer.save(flash);
+
+    let _ = match command {
+        Command::SetCountdown(_) => {
+            let _ = reply.write_str("OK ");
+            write_duration(reply, timer.countdown.remaining(&now).unwrap_or(0))
+        }
+        Command::SetMode(mode) => write!(reply, "OK {}", mode.name()),
+        _ => {
+            let _ = reply.write_str("OK ");
+            write_duration(reply, timer.stopwatch.elapsed(&now))
+        }
+    };
+}
+
+/// Current temperature, then today's range if there's a sample from today
+fn write_temperature<S: TimeSource, W: Write>(
+    reply: &mut W,
+    clock: &mut Clock<S>,
+    history: &History,
+    celsius: f32,
+) -> core::fmt::Result {
+    let unit = clock.temp_unit();
+    let symbol = unit.symbol() as char;
+
+    write!(reply, "OK {:.2}{}", to_unit(celsius, unit), symbol)?;
+
+    let today = match clock.local_datetime() {
+        Ok(local) => local.date(),
+        Err(_) => return Ok(()),
+    };
+
+    if let Some(day) = history.days().last().filter(|day| day.date == today) {
+        write!(
+            reply,
+            ", today {:.2}{} - {:.2}{}",
+            to_unit(day.min, unit),
+            symbol,
+            to_unit(day.max, unit),
+            symbol
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Local target, then the time left
+fn write_countdown<S: TimeSource, W: Write>(
+    reply: &mut W,
+    clock: &Clock<S>,
+    target: &NaiveDateTime,
+    now: &NaiveDateTime,
+) -> core::fmt::Result {
+    reply.write_str("OK ")?;
+    write_datetime(reply, &clock.time_zone().to_local(target))?;
+    reply.write_char(' ')?;
+    write_duration(reply, (target.timestamp() - now.timestamp()).max(0))
+}
+
+fn write_stopwatch<W: Write>(
+    reply: &mut W,
+    timer: &Timer,
+    now: &NaiveDateTime,
+) -> core::fmt::Result {
+    let stopwatch = &timer.stopwatch;
+
+    reply.write_str("OK ")?;
+    write_duration(reply, stopwatch.elapsed(now))?;
+
+    if stopwatch.is_running() {
+        reply.write_str(" running")?;
+    }
+
+    for (i, lap) in stopwatch.laps().iter().enumerate() {
+        reply.write_str(if i == 0 { ", laps " } else { "," })?;
+        write_duration(reply, *lap)?;
+    }
+
+    Ok(())
+}
+
+/// `H:MM:SS`, with the days in front once there are any, ie. `2d 03:04:05`
+fn write_duration<W: Write>(w: &mut W, seconds: i64) -> core::fmt::Result {
+    let days = seconds / (24 * 60 * 60);
+    let hours = seconds / 3600 % 24;
+    let minutes = seconds / 60 % 60;
+    let seconds = seconds % 60;
+
+    if days > 0 {
+        write!(w, "{}d {:02}:{:02}:{:02}", days, hours, minutes, seconds)
+    } else {
+        write!(w, "{}:{:02}:{:02}", hours, minutes, seconds)
+    }
+}
+
+/// Same format `screen add` takes
+fn write_screen<W: Write>(reply: &mut W, screen: &Screen) -> core::fmt::Result {
+    write!(
+        reply,
+        "OK {} {}",
+        screen.kind.name(),
+        screen.duration_ms / 1000
+    )?;
+
+    // Commands come in as ASCII lines
+    if let Ok(format) = core::str::from_utf8(screen.format()) {
+        reply.write_char(' ')?;
+        reply.write_str(format)?;
+    }
+
+    Ok(())
+}
+
+fn write_status<W: Write, E: core::fmt::Debug>(reply: &mut W, result: Result<(), Error<E>>) {
+    let _ = match result {
+        Ok(()) => reply.write_str("OK"),
+        Err(e) => write!(reply, "ERR {:?}", e),
+    };
+}
+
+fn write_time_zone<W: Write>(reply: &mut W, time_zone: &TimeZone) {
+    let _ = reply.write_str("OK ");
+
+    // Parsed from ASCII, so this only fails on a corrupt rule
+    if let Ok(tz) = core::str::from_utf8(time_zone.as_bytes()) {
+        let _ = reply.write_str(tz);
+    }
+}
+
+/// Collects bytes from the serial port into lines
+pub struct LineBuffer<const N: usize> {
+    line: Vec<u8, N>,
+    overflow: bool,
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        LineBuffer::new()
+    }
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        LineBuffer {
+            line: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    /// Calls `on_line` with every line completed by `bytes`. Lines longer
+    /// than `N` are dropped, a cut short command could still parse as
+    /// another, and `on_line` gets `ParseError::LineTooLong` instead.
+    pub fn feed<F>(&mut self, bytes: &[u8], mut on_line: F)
+    where
+        F: FnMut(Result<&[u8], ParseError>),
+    {
+        for b in bytes {
+            match b {
+                b'\n' | b'\r' | 0 => {
+                    if self.overflow {
+                        on_line(Err(ParseError::LineTooLong));
+                    } else if !self.line.is_empty() {
+                        on_line(Ok(&self.line));
+                    }
+
+                    self.line.clear();
+                    self.overflow = false;
+                }
+                _ => {
+                    if self.line.push(*b).is_err() && !self.overflow {
+                        self.overflow = true;
+                        log::warn!("Serial line longer than {} bytes", N);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_number(digits: &[u8]) -> Result<u32, ParseError> {
+    digits.iter().try_fold(0u32, |n, d| {
+        if d.is_ascii_digit() {
+            Ok(n * 10 + (d - b'0') as u32)
+        } else {
+            Err(ParseError::InvalidDateTime)
+        }
+    })
+}
+
+fn trim(s: &[u8]) -> &[u8] {
+    let start = s
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(s.len());
+    let end = s
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |i| i + 1);
+
+    &s[start..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_long_lines() {
+        let mut buffer = LineBuffer::<8>::new();
+        let mut lines = Vec::<Result<Vec<u8, 8>, ParseError>, 4>::new();
+
+        buffer.feed(b"time get\r\ntz set EST5EDT\ntime", |line| {
+            let _ = lines.push(line.map(|line| Vec::from_slice(line).unwrap()));
+        });
+        buffer.feed(b" get\n", |line| {
+            let _ = lines.push(line.map(|line| Vec::from_slice(line).unwrap()));
+        });
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].as_deref(), Ok(&b"time get"[..]));
+        assert_eq!(lines[1], Err(ParseError::LineTooLong));
+        assert_eq!(lines[2].as_deref(), Ok(&b"time get"[..]));
+    }
+
+    #[test]
+    fn rejects_long_alarm_days() {
+        assert_eq!(
+            Command::parse(b"alarm 1 day 15 12:00"),
+            Ok(Command::SetAlarm(
+                AlarmSlot::Alarm1,
+                Alarm {
+                    time: NaiveTime::from_hms(12, 0, 0),
+                    repeat: Repeat::Monthly(15),
+                }
+            ))
+        );
+        assert_eq!(
+            Command::parse(b"alarm 1 day 99999999999 07:00"),
+            Err(ParseError::InvalidAlarm)
+        );
+        assert_eq!(
+            Command::parse(b"alarm 1 day 032 07:00"),
+            Err(ParseError::InvalidAlarm)
+        );
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset(b"0"), Some(0));
+        assert_eq!(parse_offset(b"-0"), Some(0));
+        assert_eq!(parse_offset(b"12"), Some(12));
+        assert_eq!(parse_offset(b"-12"), Some(-12));
+        assert_eq!(parse_offset(b"127"), Some(127));
+        assert_eq!(parse_offset(b"-128"), Some(-128));
+        assert_eq!(parse_offset(b"007"), Some(7));
+    }
+
+    #[test]
+    fn rejects_bad_offsets() {
+        assert_eq!(parse_offset(b""), None);
+        assert_eq!(parse_offset(b"-"), None);
+        assert_eq!(parse_offset(b"128"), None);
+        assert_eq!(parse_offset(b"-129"), None);
+        assert_eq!(parse_offset(b"999"), None);
+        assert_eq!(parse_offset(b"1000"), None);
+        assert_eq!(parse_offset(b"+5"), None);
+        assert_eq!(parse_offset(b"1a"), None);
+        assert_eq!(parse_offset(b"--1"), None);
+    }
+}
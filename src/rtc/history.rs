@@ -0,0 +1,370 @@
+//! Temperature history from the time source's sensor. Samples are taken
+//! every `interval_secs` into a ring buffer, along with the daily (local)
+//! minimum and maximum, and can be written out as CSV.
+//!
+//! The history lives in RAM and is checkpointed to flash every
+//! `CHECKPOINT_SAMPLES` samples, so a reset loses at most that many. At the
+//! default interval that's an erase an hour, well within the flash's
+//! endurance.
+
+use super::source::TimeSource;
+use super::{
+    to_unit, write_datetime, Clock, Datelike, Error, NaiveDate, NaiveDateTime, TempUnit,
+    LINE_ENDING,
+};
+use crate::flash::{QspiFlash, TEMPERATURE_SECTOR};
+use core::fmt::Write;
+use heapless::Vec;
+
+/// A day at the default interval
+pub const MAX_SAMPLES: usize = 288;
+pub const MAX_DAYS: usize = 7;
+pub const DEFAULT_INTERVAL_SECS: i64 = 5 * 60;
+pub const CHECKPOINT_SAMPLES: u16 = 12;
+
+/// Timestamp and Celsius of a sample
+const SAMPLE_LEN: usize = 12;
+/// Days from CE, minimum and maximum
+const DAY_LEN: usize = 12;
+const RECORD_LEN: usize = 2 + MAX_SAMPLES * SAMPLE_LEN + 1 + MAX_DAYS * DAY_LEN;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// UTC
+    pub time: NaiveDateTime,
+    pub celsius: f32,
+}
+
+/// Lowest and highest sample of a day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DayRange {
+    /// Local date
+    pub date: NaiveDate,
+    pub min: f32,
+    pub max: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    interval_secs: i64,
+    /// Oldest first
+    samples: Vec<Sample, MAX_SAMPLES>,
+    /// Oldest first
+    days: Vec<DayRange, MAX_DAYS>,
+    /// Samples taken since the last `save`
+    unsaved: u16,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_INTERVAL_SECS)
+    }
+}
+
+impl History {
+    pub fn new(interval_secs: i64) -> Self {
+        History {
+            interval_secs: interval_secs.max(1),
+            samples: Vec::new(),
+            days: Vec::new(),
+            unsaved: 0,
+        }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Daily ranges, oldest first. The last one is today's once a sample was
+    /// taken today.
+    pub fn days(&self) -> &[DayRange] {
+        &self.days
+    }
+
+    pub fn latest(&self) -> Option<&Sample> {
+        self.samples.last()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.days.clear();
+        self.unsaved = 0;
+    }
+
+    /// Takes a sample if `interval_secs` have passed since the last one,
+    /// returning whether it did. Call it every loop.
+    pub fn sample<S: TimeSource>(&mut self, clock: &mut Clock<S>) -> Result<bool, Error<S::Error>> {
+        let now = clock.datetime()?;
+
+        if let Some(latest) = self.latest() {
+            let since = now.timestamp() - latest.time.timestamp();
+
+            // Also starts over if the time was set back
+            if (0..self.interval_secs).contains(&since) {
+                return Ok(false);
+            }
+        }
+
+        let celsius = match clock.celsius()? {
+            Some(celsius) => celsius,
+            None => return Ok(false),
+        };
+
+        let date = clock.time_zone().to_local(&now).date();
+        self.push(Sample { time: now, celsius }, date);
+
+        Ok(true)
+    }
+
+    /// Whether `CHECKPOINT_SAMPLES` were taken since the last `save`
+    pub fn needs_checkpoint(&self) -> bool {
+        self.unsaved >= CHECKPOINT_SAMPLES
+    }
+
+    fn push(&mut self, sample: Sample, date: NaiveDate) {
+        if self.samples.is_full() {
+            self.samples.remove(0);
+        }
+
+        let _ = self.samples.push(sample);
+        self.unsaved = self.unsaved.saturating_add(1);
+
+        match self.days.last_mut() {
+            Some(day) if day.date == date => {
+                day.min = day.min.min(sample.celsius);
+                day.max = day.max.max(sample.celsius);
+            }
+            _ => {
+                if self.days.is_full() {
+                    self.days.remove(0);
+                }
+
+                let _ = self.days.push(DayRange {
+                    date,
+                    min: sample.celsius,
+                    max: sample.celsius,
+                });
+            }
+        }
+    }
+
+    /// `time,temperature` header then a row per sample, oldest first, in
+    /// `unit`. Lines are separated by `LINE_ENDING`, with none after the
+    /// last.
+    pub fn write_csv<W: Write>(&self, w: &mut W, unit: TempUnit) -> core::fmt::Result {
+        write!(w, "time,temperature_{}", unit_name(unit))?;
+
+        for sample in &self.samples {
+            w.write_str(LINE_ENDING)?;
+            write_datetime(w, &sample.time)?;
+            write!(w, "Z,{:.2}", to_unit(sample.celsius, unit))?;
+        }
+
+        Ok(())
+    }
+
+    /// `date,min,max` header then a row per day, oldest first, in `unit`,
+    /// separated like `write_csv`
+    pub fn write_days_csv<W: Write>(&self, w: &mut W, unit: TempUnit) -> core::fmt::Result {
+        let name = unit_name(unit);
+        write!(w, "date,min_{},max_{}", name, name)?;
+
+        for day in &self.days {
+            write!(
+                w,
+                "{}{},{:.2},{:.2}",
+                LINE_ENDING,
+                day.date,
+                to_unit(day.min, unit),
+                to_unit(day.max, unit)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads the history saved with `save`, `None` if there isn't a valid
+    /// one
+    pub fn load(flash: &mut QspiFlash, interval_secs: i64) -> Option<History> {
+        let mut record = [0; RECORD_LEN];
+        let len = flash.load_record(TEMPERATURE_SECTOR, &mut record)?;
+        let record = &record[..len];
+
+        let mut history = History::new(interval_secs);
+
+        let samples = u16::from_le_bytes([*record.get(0)?, *record.get(1)?]) as usize;
+        let mut rest = &record[2..];
+
+        for _ in 0..samples {
+            let (sample, tail) = split(rest, SAMPLE_LEN)?;
+            rest = tail;
+
+            let time =
+                NaiveDateTime::from_timestamp_opt(i64::from_le_bytes(array(&sample[0..8])), 0)?;
+            let celsius = f32::from_le_bytes(array(&sample[8..12]));
+
+            history.samples.push(Sample { time, celsius }).ok()?;
+        }
+
+        let (days, mut rest) = rest.split_first()?;
+
+        for _ in 0..*days {
+            let (day, tail) = split(rest, DAY_LEN)?;
+            rest = tail;
+
+            let date = NaiveDate::from_num_days_from_ce_opt(i32::from_le_bytes(array(&day[0..4])))?;
+            let min = f32::from_le_bytes(array(&day[4..8]));
+            let max = f32::from_le_bytes(array(&day[8..12]));
+
+            history.days.push(DayRange { date, min, max }).ok()?;
+        }
+
+        Some(history)
+    }
+
+    pub fn save(&mut self, flash: &mut QspiFlash) {
+        let mut record = [0; RECORD_LEN];
+
+        record[0..2].copy_from_slice(&(self.samples.len() as u16).to_le_bytes());
+        let mut len = 2;
+
+        for sample in &self.samples {
+            let bytes = &mut record[len..len + SAMPLE_LEN];
+            bytes[0..8].copy_from_slice(&sample.time.timestamp().to_le_bytes());
+            bytes[8..12].copy_from_slice(&sample.celsius.to_le_bytes());
+            len += SAMPLE_LEN;
+        }
+
+        record[len] = self.days.len() as u8;
+        len += 1;
+
+        for day in &self.days {
+            let bytes = &mut record[len..len + DAY_LEN];
+            bytes[0..4].copy_from_slice(&day.date.num_days_from_ce().to_le_bytes());
+            bytes[4..8].copy_from_slice(&day.min.to_le_bytes());
+            bytes[8..12].copy_from_slice(&day.max.to_le_bytes());
+            len += DAY_LEN;
+        }
+
+        flash.save_record(TEMPERATURE_SECTOR, &record[..len]);
+        self.unsaved = 0;
+    }
+}
+
+fn unit_name(unit: TempUnit) -> &'static str {
+    match unit {
+        TempUnit::Celsius => "c",
+        TempUnit::Fahrenheit => "f",
+    }
+}
+
+fn split(bytes: &[u8], len: usize) -> Option<(&[u8], &[u8])> {
+    if bytes.len() < len {
+        return None;
+    }
+
+    Some(bytes.split_at(len))
+}
+
+fn array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut array = [0; N];
+    array.copy_from_slice(bytes);
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::String;
+
+    /// `seconds` after 2026-01-05 00:00:00 UTC
+    fn at(seconds: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp_opt(1_767_571_200 + seconds, 0).unwrap()
+    }
+
+    fn push(history: &mut History, seconds: i64, celsius: f32) {
+        let time = at(seconds);
+        history.push(Sample { time, celsius }, time.date());
+    }
+
+    #[test]
+    fn push_wraps_samples_and_days() {
+        let mut history = History::default();
+
+        // Every two hours, 12 a day, for 25 days
+        let count = MAX_SAMPLES as i64 + 12;
+        for n in 0..count {
+            push(&mut history, n * 2 * 3600, (n % 12) as f32);
+        }
+
+        let samples = history.samples();
+        assert_eq!(samples.len(), MAX_SAMPLES);
+        assert_eq!(samples[0].time, at(12 * 2 * 3600));
+        assert_eq!(
+            history.latest().map(|sample| sample.time),
+            Some(at((count - 1) * 2 * 3600))
+        );
+        assert!(samples.windows(2).all(|pair| pair[0].time < pair[1].time));
+
+        let days = history.days();
+        assert_eq!(days.len(), MAX_DAYS);
+        assert_eq!(days[0].date, NaiveDate::from_ymd(2026, 1, 23));
+        assert_eq!(days[MAX_DAYS - 1].date, NaiveDate::from_ymd(2026, 1, 29));
+        assert!(days.iter().all(|day| day.min == 0.0 && day.max == 11.0));
+
+        assert!(history.needs_checkpoint());
+        history.clear();
+        assert!(history.samples().is_empty() && history.days().is_empty());
+        assert!(!history.needs_checkpoint());
+    }
+
+    #[test]
+    fn push_tracks_the_day_range() {
+        let mut history = History::default();
+
+        push(&mut history, 0, 20.5);
+        push(&mut history, 3600, 18.0);
+        push(&mut history, 7200, 22.25);
+        // Next day
+        push(&mut history, 24 * 3600, -3.25);
+
+        assert_eq!(
+            history.days(),
+            [
+                DayRange {
+                    date: NaiveDate::from_ymd(2026, 1, 5),
+                    min: 18.0,
+                    max: 22.25,
+                },
+                DayRange {
+                    date: NaiveDate::from_ymd(2026, 1, 6),
+                    min: -3.25,
+                    max: -3.25,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let mut history = History::default();
+        push(&mut history, 0, 20.5);
+        push(&mut history, 24 * 3600, -3.25);
+
+        let mut csv = String::<256>::new();
+        history.write_csv(&mut csv, TempUnit::Celsius).unwrap();
+        assert_eq!(
+            csv,
+            "time,temperature_c\r\n2026-01-05T00:00:00Z,20.50\r\n2026-01-06T00:00:00Z,-3.25"
+        );
+
+        let mut csv = String::<256>::new();
+        history
+            .write_days_csv(&mut csv, TempUnit::Fahrenheit)
+            .unwrap();
+        assert_eq!(
+            csv,
+            "date,min_f,max_f\r\n2026-01-05,68.90,68.90\r\n2026-01-06,26.15,26.15"
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! The SAMD51's own RTC in calendar mode (MODE2), for boards without a
+//! DS3231. It's clocked from the 1.024 kHz output of the 32 kHz crystal that
+//! `GenericClockController::with_external_32kosc` starts, divided down to
+//! 1 Hz. It keeps running through a system reset but not a power cycle, and
+//! has no temperature sensor. FREQCORR stands in for the DS3231's aging
+//! offset.
+//!
+//! Alarms raise the `RTC` interrupt, which has to call `on_interrupt`.
+
+use super::alarm::{self, AlarmSlot, Repeat};
+use super::source::TimeSource;
+use super::{Datelike, Error, NaiveDate, NaiveDateTime, Timelike};
+use core::convert::Infallible;
+use cortex_m::peripheral::NVIC;
+use metro_m4::pac::{interrupt, MCLK, OSC32KCTRL, RTC};
+
+/// Year the 6 bit YEAR field counts from
+const REFERENCE_YEAR: i32 = 2000;
+const MAX_YEAR: i32 = REFERENCE_YEAR + 63;
+
+/// Kept in BKUP0 once the time is set. The backup registers are cleared
+/// along with the RTC when power is lost.
+const TIME_SET_MAGIC: u32 = 0x5254_4331;
+
+/// Alarms that went off, set from the interrupt handler
+static mut MATCHED: [bool; 2] = [false; 2];
+
+pub struct InternalRtc {
+    rtc: RTC,
+}
+
+impl InternalRtc {
+    /// Leaves the RTC alone if it's already running from before a reset, so
+    /// the time survives it
+    pub fn new(rtc: RTC, mclk: &mut MCLK, osc32kctrl: &mut OSC32KCTRL, nvic: &mut NVIC) -> Self {
+        mclk.apbamask.modify(|_, w| w.rtc_().set_bit());
+
+        let mode2 = rtc.mode2();
+        let ctrla = mode2.ctrla.read();
+
+        if !(ctrla.enable().bit_is_set() && ctrla.mode().is_clock()) {
+            // 1.024 kHz from the crystal. RTCCTRL can only change while the
+            // RTC is disabled.
+            osc32kctrl.xosc32k.modify(|_, w| w.en1k().set_bit());
+            osc32kctrl.rtcctrl.write(|w| w.rtcsel().xosc1k());
+
+            mode2.ctrla.write(|w| w.swrst().set_bit());
+            while mode2.syncbusy.read().swrst().bit_is_set() {}
+
+            // 24 hour, 1 Hz, with reads of CLOCK kept in sync
+            mode2.ctrla.write(|w| {
+                w.mode().clock();
+                w.prescaler().div1024();
+                w.clocksync().set_bit()
+            });
+            while mode2.syncbusy.read().clocksync().bit_is_set() {}
+
+            mode2.ctrla.modify(|_, w| w.enable().set_bit());
+            while mode2.syncbusy.read().enable().bit_is_set() {}
+        }
+
+        unsafe {
+            nvic.set_priority(interrupt::RTC, 1);
+            NVIC::unmask(interrupt::RTC);
+        }
+
+        InternalRtc { rtc }
+    }
+
+    pub fn release(self) -> RTC {
+        self.rtc
+    }
+}
+
+/// Call from the `RTC` interrupt handler
+pub fn on_interrupt() {
+    let mode2 = unsafe { (*RTC::ptr()).mode2() };
+    let flags = mode2.intflag.read();
+
+    let alarm0 = flags.alarm0().bit_is_set();
+    let alarm1 = flags.alarm1().bit_is_set();
+
+    // Writing ones clears the flags
+    mode2.intflag.write(|w| {
+        w.alarm0().bit(alarm0);
+        w.alarm1().bit(alarm1)
+    });
+
+    cortex_m::interrupt::free(|_| unsafe {
+        MATCHED[0] |= alarm0;
+        MATCHED[1] |= alarm1;
+    });
+
+    if alarm0 || alarm1 {
+        alarm::notify();
+    }
+}
+
+impl TimeSource for InternalRtc {
+    type Error = Infallible;
+
+    fn datetime(&mut self) -> Result<NaiveDateTime, Error<Infallible>> {
+        let clock = self.rtc.mode2().clock.read();
+
+        NaiveDate::from_ymd_opt(
+            REFERENCE_YEAR + clock.year().bits() as i32,
+            clock.month().bits() as u32,
+            clock.day().bits() as u32,
+        )
+        .and_then(|date| {
+            date.and_hms_opt(
+                clock.hour().bits() as u32,
+                clock.minute().bits() as u32,
+                clock.second().bits() as u32,
+            )
+        })
+        .ok_or(Error::InvalidDeviceState)
+    }
+
+    fn set_datetime(&mut self, datetime: &NaiveDateTime) -> Result<(), Error<Infallible>> {
+        if !(REFERENCE_YEAR..=MAX_YEAR).contains(&datetime.year()) {
+            return Err(Error::InvalidDateTime);
+        }
+
+        let mode2 = self.rtc.mode2();
+
+        mode2.clock.write(|w| unsafe {
+            w.year().bits((datetime.year() - REFERENCE_YEAR) as u8);
+            w.month().bits(datetime.month() as u8);
+            w.day().bits(datetime.day() as u8);
+            w.hour().bits(datetime.hour() as u8);
+            w.minute().bits(datetime.minute() as u8);
+            w.second().bits(datetime.second() as u8)
+        });
+        while mode2.syncbusy.read().clock().bit_is_set() {}
+
+        mode2.bkup[0].write(|w| unsafe { w.bits(TIME_SET_MAGIC) });
+
+        Ok(())
+    }
+
+    fn lost_power(&mut self) -> Result<bool, Error<Infallible>> {
+        Ok(self.rtc.mode2().bkup[0].read().bits() != TIME_SET_MAGIC)
+    }
+
+    fn temperature(&mut self) -> Result<Option<f32>, Error<Infallible>> {
+        Ok(None)
+    }
+
+    fn set_alarm(
+        &mut self,
+        slot: AlarmSlot,
+        at: &NaiveDateTime,
+        _repeat: Repeat,
+    ) -> Result<(), Error<Infallible>> {
+        if !(REFERENCE_YEAR..=MAX_YEAR).contains(&at.year()) {
+            return Err(Error::InvalidDateTime);
+        }
+
+        let mode2 = self.rtc.mode2();
+
+        // The calendar has no weekday to match on, so always match the full
+        // date and rely on `Clock` re-arming it
+        macro_rules! arm {
+            ($alarm:ident, $mask:ident) => {{
+                mode2.$alarm.write(|w| unsafe {
+                    w.year().bits((at.year() - REFERENCE_YEAR) as u8);
+                    w.month().bits(at.month() as u8);
+                    w.day().bits(at.day() as u8);
+                    w.hour().bits(at.hour() as u8);
+                    w.minute().bits(at.minute() as u8);
+                    w.second().bits(at.second() as u8)
+                });
+                while mode2.syncbusy.read().$alarm().bit_is_set() {}
+
+                mode2.$mask.write(|w| w.sel().yymmddhhmmss());
+                while mode2.syncbusy.read().$mask().bit_is_set() {}
+
+                mode2.intflag.write(|w| w.$alarm().set_bit());
+                mode2.intenset.write(|w| w.$alarm().set_bit());
+            }};
+        }
+
+        match slot {
+            AlarmSlot::Alarm1 => arm!(alarm0, mask0),
+            AlarmSlot::Alarm2 => arm!(alarm1, mask1),
+        }
+
+        take_matched(slot);
+
+        Ok(())
+    }
+
+    fn clear_alarm(&mut self, slot: AlarmSlot) -> Result<(), Error<Infallible>> {
+        let mode2 = self.rtc.mode2();
+
+        match slot {
+            AlarmSlot::Alarm1 => {
+                mode2.intenclr.write(|w| w.alarm0().set_bit());
+                mode2.mask0.write(|w| w.sel().off());
+                while mode2.syncbusy.read().mask0().bit_is_set() {}
+            }
+            AlarmSlot::Alarm2 => {
+                mode2.intenclr.write(|w| w.alarm1().set_bit());
+                mode2.mask1.write(|w| w.sel().off());
+                while mode2.syncbusy.read().mask1().bit_is_set() {}
+            }
+        }
+
+        take_matched(slot);
+
+        Ok(())
+    }
+
+    fn take_alarm(&mut self, slot: AlarmSlot) -> Result<bool, Error<Infallible>> {
+        Ok(take_matched(slot))
+    }
+
+    fn aging_offset(&mut self) -> Result<i8, Error<Infallible>> {
+        let freqcorr = self.rtc.mode2().freqcorr.read();
+        let value = freqcorr.value().bits() as i8;
+
+        // SIGN set speeds the clock up
+        Ok(if freqcorr.sign().bit_is_set() {
+            -value
+        } else {
+            value
+        })
+    }
+
+    fn set_aging_offset(&mut self, offset: i8) -> Result<(), Error<Infallible>> {
+        let mode2 = self.rtc.mode2();
+
+        // VALUE is 7 bits
+        let value = offset.unsigned_abs().min(127);
+
+        mode2.freqcorr.write(|w| unsafe {
+            w.value().bits(value);
+            w.sign().bit(offset < 0)
+        });
+        while mode2.syncbusy.read().freqcorr().bit_is_set() {}
+
+        Ok(())
+    }
+
+    /// One part in 2^20
+    fn ppm_per_step(&self) -> f32 {
+        0.954
+    }
+}
+
+fn take_matched(slot: AlarmSlot) -> bool {
+    cortex_m::interrupt::free(|_| unsafe { core::mem::replace(&mut MATCHED[slot.index()], false) })
+}
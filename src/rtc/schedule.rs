@@ -0,0 +1,468 @@
+//! Playlist of clock screens, each shown for its duration before moving on to
+//! the next. Screens are filled from a format string, see `template`, except
+//! messages which are shown as written and scroll when they don't fit.
+//! Countdown screens show the timer's countdown, see `timer`.
+
+use super::source::TimeSource;
+use super::template::{self, TemplateError};
+use super::timer::{self, Countdown};
+use super::{Clock, Error};
+use crate::alphanum::text::DisplayText;
+use crate::flash::{QspiFlash, SCHEDULE_SECTOR};
+use heapless::Vec;
+
+pub const MAX_SCREENS: usize = 8;
+pub const MAX_FORMAT_LEN: usize = 128;
+/// How long a long message takes to scroll one cell
+pub const SCROLL_STEP_MS: u32 = 200;
+
+/// Duration of the message screen `set_message` adds
+const DEFAULT_MESSAGE_MS: u32 = 10_000;
+
+/// First byte of a saved playlist. Version 1 countdown screens had a target
+/// of their own, it's dropped when loading one.
+const RECORD_VERSION: u8 = 2;
+/// Kind, duration and format length of a saved screen
+const SCREEN_HEADER_LEN: usize = 6;
+/// Kind, duration, countdown target and format length, in version 1
+const V1_SCREEN_HEADER_LEN: usize = 14;
+const RECORD_LEN: usize = 2 + MAX_SCREENS * (V1_SCREEN_HEADER_LEN + MAX_FORMAT_LEN);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenKind {
+    Time,
+    Date,
+    Temperature,
+    Message,
+    /// Time left on the timer's countdown
+    Countdown,
+}
+
+impl ScreenKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScreenKind::Time => "time",
+            ScreenKind::Date => "date",
+            ScreenKind::Temperature => "temp",
+            ScreenKind::Message => "message",
+            ScreenKind::Countdown => "countdown",
+        }
+    }
+
+    fn default_format(&self) -> &'static [u8] {
+        match self {
+            ScreenKind::Time => template::TIME_12H,
+            ScreenKind::Date => template::DATE_WITH_TEMPERATURE,
+            ScreenKind::Temperature => b"%T%U",
+            ScreenKind::Message => b"",
+            ScreenKind::Countdown => timer::COUNTDOWN,
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            ScreenKind::Time => 0,
+            ScreenKind::Date => 1,
+            ScreenKind::Temperature => 2,
+            ScreenKind::Message => 3,
+            ScreenKind::Countdown => 4,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<ScreenKind> {
+        match id {
+            0 => Some(ScreenKind::Time),
+            1 => Some(ScreenKind::Date),
+            2 => Some(ScreenKind::Temperature),
+            3 => Some(ScreenKind::Message),
+            4 => Some(ScreenKind::Countdown),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleError {
+    TooManyScreens,
+    FormatTooLong,
+    /// Format with a field `template` doesn't know
+    Format(TemplateError),
+    /// No screen at that index
+    OutOfRange,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    pub kind: ScreenKind,
+    pub duration_ms: u32,
+    format: Vec<u8, MAX_FORMAT_LEN>,
+}
+
+impl Screen {
+    /// An empty `format` uses the kind's default. Formats of messages are
+    /// shown as written, any others have to be valid templates.
+    pub fn new(kind: ScreenKind, duration_ms: u32, format: &[u8]) -> Result<Self, ScheduleError> {
+        let format = if format.is_empty() {
+            kind.default_format()
+        } else {
+            format
+        };
+
+        if kind != ScreenKind::Message {
+            template::validate(format).map_err(ScheduleError::Format)?;
+        }
+
+        Ok(Screen {
+            kind,
+            duration_ms,
+            format: Vec::from_slice(format).map_err(|_| ScheduleError::FormatTooLong)?,
+        })
+    }
+
+    pub fn format(&self) -> &[u8] {
+        &self.format
+    }
+
+    /// How long the screen stays up on a display `width` cells wide. A
+    /// message too long to fit stays up for whole scrolls, at least one, so
+    /// its end is always shown.
+    pub fn shown_ms(&self, width: usize) -> u32 {
+        if self.kind != ScreenKind::Message || self.format.len() <= width {
+            return self.duration_ms;
+        }
+
+        let scroll_ms = (self.format.len() + width) as u32 * SCROLL_STEP_MS;
+        let scrolls = (self.duration_ms.saturating_add(scroll_ms - 1) / scroll_ms).max(1);
+
+        scrolls.saturating_mul(scroll_ms)
+    }
+
+    fn render<S: TimeSource>(
+        &self,
+        clock: &mut Clock<S>,
+        countdown: &Countdown,
+        width: usize,
+        elapsed_ms: u32,
+    ) -> Result<DisplayText, Error<S::Error>> {
+        let mut text = DisplayText::new();
+
+        if self.kind == ScreenKind::Message {
+            scroll(&self.format, width, elapsed_ms, &mut text);
+            return Ok(text);
+        }
+
+        let mut context = clock.context(&self.format)?;
+
+        if self.kind == ScreenKind::Countdown {
+            match countdown.remaining(&clock.datetime()?) {
+                Some(remaining) => context.remaining = Some(remaining),
+                None => {
+                    text.push_str(timer::NO_COUNTDOWN);
+                    return Ok(text);
+                }
+            }
+        }
+
+        template::render(&self.format, &context, &mut text);
+
+        Ok(text)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    screens: Vec<Screen, MAX_SCREENS>,
+    current: usize,
+    /// Time spent on the current screen
+    elapsed_ms: u32,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Schedule::default()
+    }
+
+    /// Time then date, five seconds each
+    pub fn default_playlist() -> Self {
+        let mut schedule = Schedule::new();
+
+        for kind in [ScreenKind::Time, ScreenKind::Date] {
+            if let Ok(screen) = Screen::new(kind, 5000, b"") {
+                let _ = schedule.add(screen);
+            }
+        }
+
+        schedule
+    }
+
+    pub fn screens(&self) -> &[Screen] {
+        &self.screens
+    }
+
+    pub fn add(&mut self, screen: Screen) -> Result<(), ScheduleError> {
+        self.screens
+            .push(screen)
+            .map_err(|_| ScheduleError::TooManyScreens)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<Screen, ScheduleError> {
+        if index >= self.screens.len() {
+            return Err(ScheduleError::OutOfRange);
+        }
+
+        let screen = self.screens.remove(index);
+        self.restart();
+
+        Ok(screen)
+    }
+
+    pub fn clear(&mut self) {
+        self.screens.clear();
+        self.restart();
+    }
+
+    /// Sets the text of the first message screen, adding one if there isn't
+    /// one yet. Text past `MAX_FORMAT_LEN` is cut off, returns how much was
+    /// kept.
+    pub fn set_message(&mut self, text: &[u8]) -> Result<usize, ScheduleError> {
+        let text = &text[..text.len().min(MAX_FORMAT_LEN)];
+        let screen = Screen::new(ScreenKind::Message, DEFAULT_MESSAGE_MS, text)?;
+
+        match self
+            .screens
+            .iter_mut()
+            .find(|screen| screen.kind == ScreenKind::Message)
+        {
+            Some(message) => message.format = screen.format,
+            None => self.add(screen)?,
+        }
+
+        Ok(text.len())
+    }
+
+    /// Back to the first screen
+    pub fn restart(&mut self) {
+        self.current = 0;
+        self.elapsed_ms = 0;
+    }
+
+    pub fn current(&self) -> Option<&Screen> {
+        self.screens.get(self.current)
+    }
+
+    /// Advances by `elapsed_ms` on a display `width` cells wide, returning
+    /// whether it moved on to another screen
+    pub fn tick(&mut self, elapsed_ms: u32, width: usize) -> bool {
+        let duration_ms = match self.current() {
+            Some(screen) => screen.shown_ms(width),
+            None => return false,
+        };
+
+        self.elapsed_ms += elapsed_ms;
+
+        if self.elapsed_ms < duration_ms {
+            return false;
+        }
+
+        self.current = (self.current + 1) % self.screens.len();
+        self.elapsed_ms = 0;
+
+        true
+    }
+
+    /// The current screen, `width` cells wide. Blank without any screens.
+    pub fn render<S: TimeSource>(
+        &self,
+        clock: &mut Clock<S>,
+        countdown: &Countdown,
+        width: usize,
+    ) -> Result<DisplayText, Error<S::Error>> {
+        match self.current() {
+            Some(screen) => screen.render(clock, countdown, width, self.elapsed_ms),
+            None => Ok(DisplayText::new()),
+        }
+    }
+
+    /// Loads the playlist saved with `save`, `None` if there isn't a valid
+    /// one
+    pub fn load(flash: &mut QspiFlash) -> Option<Schedule> {
+        let mut record = [0; RECORD_LEN];
+        let len = flash.load_record(SCHEDULE_SECTOR, &mut record)?;
+
+        Schedule::from_record(&record[..len])
+    }
+
+    pub fn save(&self, flash: &mut QspiFlash) {
+        let mut record = [0; RECORD_LEN];
+        let len = self.to_record(&mut record);
+
+        flash.save_record(SCHEDULE_SECTOR, &record[..len]);
+    }
+
+    fn from_record(mut record: &[u8]) -> Option<Schedule> {
+        let (version, rest) = record.split_first()?;
+
+        let header_len = match *version {
+            RECORD_VERSION => SCREEN_HEADER_LEN,
+            1 => V1_SCREEN_HEADER_LEN,
+            _ => return None,
+        };
+
+        let (count, rest) = rest.split_first()?;
+        record = rest;
+
+        let mut schedule = Schedule::new();
+
+        for _ in 0..*count {
+            if record.len() < header_len {
+                return None;
+            }
+
+            let (header, rest) = record.split_at(header_len);
+
+            let kind = ScreenKind::from_id(header[0])?;
+            let mut duration_ms = [0; 4];
+            duration_ms.copy_from_slice(&header[1..5]);
+            let format_len = header[header_len - 1] as usize;
+
+            let format = rest.get(..format_len)?;
+            record = &rest[format_len..];
+
+            let screen = Screen::new(kind, u32::from_le_bytes(duration_ms), format).ok()?;
+            schedule.add(screen).ok()?;
+        }
+
+        Some(schedule)
+    }
+
+    /// Writes the playlist into `record`, returning the length used
+    fn to_record(&self, record: &mut [u8; RECORD_LEN]) -> usize {
+        record[0] = RECORD_VERSION;
+        record[1] = self.screens.len() as u8;
+
+        let mut len = 2;
+
+        for screen in &self.screens {
+            let header = &mut record[len..len + SCREEN_HEADER_LEN];
+            header[0] = screen.kind.id();
+            header[1..5].copy_from_slice(&screen.duration_ms.to_le_bytes());
+            header[5] = screen.format.len() as u8;
+            len += SCREEN_HEADER_LEN;
+
+            record[len..len + screen.format.len()].copy_from_slice(&screen.format);
+            len += screen.format.len();
+        }
+
+        len
+    }
+}
+
+/// `message` as is if it fits in `width` cells, otherwise scrolling in from
+/// the right one cell every `SCROLL_STEP_MS`
+fn scroll(message: &[u8], width: usize, elapsed_ms: u32, text: &mut DisplayText) {
+    if message.len() <= width {
+        for b in message {
+            text.push(*b);
+        }
+        return;
+    }
+
+    // Starts blank, then runs until the message is gone again
+    let offset = (elapsed_ms / SCROLL_STEP_MS) as usize % (message.len() + width);
+
+    for cell in offset..offset + width {
+        let b = cell
+            .checked_sub(width)
+            .and_then(|i| message.get(i))
+            .unwrap_or(&b' ');
+
+        text.push(*b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        let mut schedule = Schedule::default_playlist();
+        schedule
+            .add(Screen::new(ScreenKind::Countdown, 10_000, b"%D days").unwrap())
+            .unwrap();
+        schedule.set_message(b"hello").unwrap();
+
+        let mut record = [0; RECORD_LEN];
+        let len = schedule.to_record(&mut record);
+
+        let loaded = Schedule::from_record(&record[..len]).unwrap();
+        assert_eq!(loaded.screens(), schedule.screens());
+    }
+
+    #[test]
+    fn loads_version_1_records() {
+        let mut record = [0u8; 2 + 2 * V1_SCREEN_HEADER_LEN + 7];
+        record[0] = 1;
+        record[1] = 2;
+
+        // Time with the default format
+        record[2] = 0;
+        record[3..7].copy_from_slice(&5000u32.to_le_bytes());
+
+        // Countdown with a target of its own and a format
+        let countdown = &mut record[2 + V1_SCREEN_HEADER_LEN..];
+        countdown[0] = 4;
+        countdown[1..5].copy_from_slice(&10_000u32.to_le_bytes());
+        countdown[5..13].copy_from_slice(&1_798_156_800i64.to_le_bytes());
+        countdown[13] = 7;
+        countdown[14..21].copy_from_slice(b"%D days");
+
+        let loaded = Schedule::from_record(&record).unwrap();
+        let screens = loaded.screens();
+
+        assert_eq!(screens.len(), 2);
+        assert_eq!(screens[0].kind, ScreenKind::Time);
+        assert_eq!(screens[0].format(), template::TIME_12H);
+        assert_eq!(screens[1].kind, ScreenKind::Countdown);
+        assert_eq!(screens[1].duration_ms, 10_000);
+        assert_eq!(screens[1].format(), b"%D days");
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        assert!(Schedule::from_record(&[0, 0]).is_none());
+        assert!(Schedule::from_record(&[RECORD_VERSION + 1, 0]).is_none());
+        assert!(Schedule::from_record(&[RECORD_VERSION, 0]).is_some());
+    }
+
+    #[test]
+    fn long_messages_scroll_to_the_end() {
+        let mut schedule = Schedule::default_playlist();
+        schedule.set_message(&[b'x'; 40]).unwrap();
+
+        let message = &schedule.screens()[2];
+        assert_eq!(message.shown_ms(12), (40 + 12) * SCROLL_STEP_MS);
+        assert_eq!(message.shown_ms(40), DEFAULT_MESSAGE_MS);
+
+        // Past the time and date screens
+        assert!(schedule.tick(5000, 12));
+        assert!(schedule.tick(5000, 12));
+        assert_eq!(schedule.current().unwrap().kind, ScreenKind::Message);
+
+        assert!(!schedule.tick(DEFAULT_MESSAGE_MS, 12));
+        assert!(!schedule.tick(200, 12));
+        assert!(schedule.tick(SCROLL_STEP_MS, 12));
+        assert_eq!(schedule.current().unwrap().kind, ScreenKind::Time);
+    }
+
+    #[test]
+    fn message_scrolls_stay_whole() {
+        let long = Screen::new(ScreenKind::Message, 30_000, &[b'x'; 128]).unwrap();
+        let scroll_ms = (128 + 12) * SCROLL_STEP_MS;
+
+        assert_eq!(long.shown_ms(12), 2 * scroll_ms);
+
+        let short = Screen::new(ScreenKind::Message, 3000, b"hi").unwrap();
+        assert_eq!(short.shown_ms(12), 3000);
+    }
+}
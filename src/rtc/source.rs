@@ -0,0 +1,333 @@
+//! Where `Clock` gets the time from. Sources hold UTC and are armed with the
+//! exact UTC time an alarm goes off next, local time is left to `Clock`.
+
+use super::alarm::{AlarmSlot, Repeat};
+use super::internal::InternalRtc;
+use super::{Datelike, Error, NaiveDateTime, Timelike};
+use core::convert::Infallible;
+use ds323x::ic::DS3231;
+use ds323x::interface::I2cInterface;
+use ds323x::{
+    Alarm1Matching, Alarm2Matching, DayAlarm1, DayAlarm2, Ds323x, Hours, Rtcc, WeekdayAlarm1,
+    WeekdayAlarm2,
+};
+use embedded_hal::blocking::i2c;
+
+pub trait TimeSource {
+    type Error: core::fmt::Debug;
+
+    /// Current UTC time
+    fn datetime(&mut self) -> Result<NaiveDateTime, Error<Self::Error>>;
+
+    fn set_datetime(&mut self, datetime: &NaiveDateTime) -> Result<(), Error<Self::Error>>;
+
+    /// Whether the time stopped since it was last set, so it can't be trusted
+    fn lost_power(&mut self) -> Result<bool, Error<Self::Error>>;
+
+    /// Temperature in Celsius, `None` without a sensor
+    fn temperature(&mut self) -> Result<Option<f32>, Error<Self::Error>>;
+
+    /// Arms `slot` to go off at `at`, UTC, and enables its interrupt. Sources
+    /// may also match later occurrences of `repeat`, but `Clock` re-arms the
+    /// alarm every time it goes off either way.
+    fn set_alarm(
+        &mut self,
+        slot: AlarmSlot,
+        at: &NaiveDateTime,
+        repeat: Repeat,
+    ) -> Result<(), Error<Self::Error>>;
+
+    fn clear_alarm(&mut self, slot: AlarmSlot) -> Result<(), Error<Self::Error>>;
+
+    /// Whether `slot` went off, clearing its flag
+    fn take_alarm(&mut self, slot: AlarmSlot) -> Result<bool, Error<Self::Error>>;
+
+    /// Oscillator trim, positive slows the clock down
+    fn aging_offset(&mut self) -> Result<i8, Error<Self::Error>>;
+
+    fn set_aging_offset(&mut self, offset: i8) -> Result<(), Error<Self::Error>>;
+
+    /// Frequency change of one aging offset step
+    fn ppm_per_step(&self) -> f32;
+}
+
+/// DS3231 breakout on I2C, address 0x68
+pub struct Ds3231<I2C> {
+    rtc: Ds323x<I2cInterface<I2C>, DS3231>,
+}
+
+impl<I2C, E> Ds3231<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    pub fn new(i2c: I2C) -> Self {
+        Ds3231 {
+            rtc: Ds323x::new_ds3231(i2c),
+        }
+    }
+
+    /// Whether the DS3231 answers on the bus
+    pub fn is_present(&mut self) -> bool {
+        self.rtc.has_been_stopped().is_ok()
+    }
+
+    pub fn release(self) -> I2C {
+        self.rtc.destroy_ds3231()
+    }
+}
+
+impl<I2C, E> TimeSource for Ds3231<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    type Error = E;
+
+    fn datetime(&mut self) -> Result<NaiveDateTime, Error<E>> {
+        Ok(self.rtc.get_datetime()?)
+    }
+
+    fn set_datetime(&mut self, datetime: &NaiveDateTime) -> Result<(), Error<E>> {
+        self.rtc.set_datetime(datetime)?;
+
+        // The time is good again
+        self.rtc.clear_has_been_stopped_flag()?;
+
+        Ok(())
+    }
+
+    fn lost_power(&mut self) -> Result<bool, Error<E>> {
+        Ok(self.rtc.has_been_stopped()?)
+    }
+
+    fn temperature(&mut self) -> Result<Option<f32>, Error<E>> {
+        Ok(Some(self.rtc.get_temperature()?))
+    }
+
+    fn set_alarm(
+        &mut self,
+        slot: AlarmSlot,
+        at: &NaiveDateTime,
+        repeat: Repeat,
+    ) -> Result<(), Error<E>> {
+        let hour = Hours::H24(at.hour() as u8);
+        let minute = at.minute() as u8;
+        let second = at.second() as u8;
+        let day = at.day() as u8;
+        // Same numbering `set_datetime` writes to the DS3231
+        let weekday = at.weekday().number_from_sunday() as u8;
+
+        // The DS3231 repeats these on its own, so alarms left armed from
+        // before a reset keep going off
+        match (slot, repeat) {
+            (AlarmSlot::Alarm1, Repeat::Daily) => self.rtc.set_alarm1_day(
+                DayAlarm1 {
+                    day,
+                    hour,
+                    minute,
+                    second,
+                },
+                Alarm1Matching::HoursMinutesAndSecondsMatch,
+            )?,
+            (AlarmSlot::Alarm1, Repeat::Weekly(_)) => self.rtc.set_alarm1_weekday(
+                WeekdayAlarm1 {
+                    weekday,
+                    hour,
+                    minute,
+                    second,
+                },
+                Alarm1Matching::AllMatch,
+            )?,
+            (AlarmSlot::Alarm1, Repeat::Monthly(_)) => self.rtc.set_alarm1_day(
+                DayAlarm1 {
+                    day,
+                    hour,
+                    minute,
+                    second,
+                },
+                Alarm1Matching::AllMatch,
+            )?,
+            (AlarmSlot::Alarm2, Repeat::Daily) => self.rtc.set_alarm2_day(
+                DayAlarm2 { day, hour, minute },
+                Alarm2Matching::HoursAndMinutesMatch,
+            )?,
+            (AlarmSlot::Alarm2, Repeat::Weekly(_)) => self.rtc.set_alarm2_weekday(
+                WeekdayAlarm2 {
+                    weekday,
+                    hour,
+                    minute,
+                },
+                Alarm2Matching::AllMatch,
+            )?,
+            (AlarmSlot::Alarm2, Repeat::Monthly(_)) => self
+                .rtc
+                .set_alarm2_day(DayAlarm2 { day, hour, minute }, Alarm2Matching::AllMatch)?,
+        }
+
+        self.rtc.use_int_sqw_output_as_interrupt()?;
+
+        match slot {
+            AlarmSlot::Alarm1 => {
+                self.rtc.clear_alarm1_matched_flag()?;
+                self.rtc.enable_alarm1_interrupts()?;
+            }
+            AlarmSlot::Alarm2 => {
+                self.rtc.clear_alarm2_matched_flag()?;
+                self.rtc.enable_alarm2_interrupts()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clear_alarm(&mut self, slot: AlarmSlot) -> Result<(), Error<E>> {
+        match slot {
+            AlarmSlot::Alarm1 => {
+                self.rtc.disable_alarm1_interrupts()?;
+                self.rtc.clear_alarm1_matched_flag()?;
+            }
+            AlarmSlot::Alarm2 => {
+                self.rtc.disable_alarm2_interrupts()?;
+                self.rtc.clear_alarm2_matched_flag()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn take_alarm(&mut self, slot: AlarmSlot) -> Result<bool, Error<E>> {
+        let matched = match slot {
+            AlarmSlot::Alarm1 => self.rtc.has_alarm1_matched()?,
+            AlarmSlot::Alarm2 => self.rtc.has_alarm2_matched()?,
+        };
+
+        if matched {
+            match slot {
+                AlarmSlot::Alarm1 => self.rtc.clear_alarm1_matched_flag()?,
+                AlarmSlot::Alarm2 => self.rtc.clear_alarm2_matched_flag()?,
+            }
+        }
+
+        Ok(matched)
+    }
+
+    fn aging_offset(&mut self) -> Result<i8, Error<E>> {
+        Ok(self.rtc.get_aging_offset()?)
+    }
+
+    fn set_aging_offset(&mut self, offset: i8) -> Result<(), Error<E>> {
+        self.rtc.set_aging_offset(offset)?;
+
+        // The offset only applies from the next conversion, which can be up
+        // to 64 seconds away otherwise
+        Ok(self.rtc.convert_temperature()?)
+    }
+
+    /// Typical at 25°C, per the datasheet
+    fn ppm_per_step(&self) -> f32 {
+        0.1
+    }
+}
+
+/// The DS3231 when the board has one, the SAMD51's own RTC otherwise
+pub enum AnySource<I2C> {
+    Ds3231(Ds3231<I2C>),
+    Internal(InternalRtc),
+}
+
+impl<I2C, E> AnySource<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    /// Uses `ds3231` if it answers on the bus, otherwise the RTC `internal`
+    /// sets up
+    pub fn detect<F>(mut ds3231: Ds3231<I2C>, internal: F) -> Self
+    where
+        F: FnOnce() -> InternalRtc,
+    {
+        if ds3231.is_present() {
+            AnySource::Ds3231(ds3231)
+        } else {
+            log::warn!("No DS3231 found, using the internal RTC");
+
+            AnySource::Internal(internal())
+        }
+    }
+}
+
+macro_rules! delegate {
+    ($self:ident, $source:ident => $call:expr) => {
+        match $self {
+            AnySource::Ds3231($source) => $call,
+            AnySource::Internal($source) => $call.map_err(widen),
+        }
+    };
+}
+
+impl<I2C, E> TimeSource for AnySource<I2C>
+where
+    I2C: i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: core::fmt::Debug,
+{
+    type Error = E;
+
+    fn datetime(&mut self) -> Result<NaiveDateTime, Error<E>> {
+        delegate!(self, source => source.datetime())
+    }
+
+    fn set_datetime(&mut self, datetime: &NaiveDateTime) -> Result<(), Error<E>> {
+        delegate!(self, source => source.set_datetime(datetime))
+    }
+
+    fn lost_power(&mut self) -> Result<bool, Error<E>> {
+        delegate!(self, source => source.lost_power())
+    }
+
+    fn temperature(&mut self) -> Result<Option<f32>, Error<E>> {
+        delegate!(self, source => source.temperature())
+    }
+
+    fn set_alarm(
+        &mut self,
+        slot: AlarmSlot,
+        at: &NaiveDateTime,
+        repeat: Repeat,
+    ) -> Result<(), Error<E>> {
+        delegate!(self, source => source.set_alarm(slot, at, repeat))
+    }
+
+    fn clear_alarm(&mut self, slot: AlarmSlot) -> Result<(), Error<E>> {
+        delegate!(self, source => source.clear_alarm(slot))
+    }
+
+    fn take_alarm(&mut self, slot: AlarmSlot) -> Result<bool, Error<E>> {
+        delegate!(self, source => source.take_alarm(slot))
+    }
+
+    fn aging_offset(&mut self) -> Result<i8, Error<E>> {
+        delegate!(self, source => source.aging_offset())
+    }
+
+    fn set_aging_offset(&mut self, offset: i8) -> Result<(), Error<E>> {
+        delegate!(self, source => source.set_aging_offset(offset))
+    }
+
+    fn ppm_per_step(&self) -> f32 {
+        match self {
+            AnySource::Ds3231(source) => source.ppm_per_step(),
+            AnySource::Internal(source) => source.ppm_per_step(),
+        }
+    }
+}
+
+/// Errors of a source without a bus can be returned from any source
+fn widen<E>(error: Error<Infallible>) -> Error<E> {
+    match error {
+        Error::I2c(never) => match never {},
+        Error::InvalidDateTime => Error::InvalidDateTime,
+        Error::InvalidDeviceState => Error::InvalidDeviceState,
+        Error::Calibration(e) => Error::Calibration(e),
+    }
+}
@@ -0,0 +1,381 @@
+//! Format strings for clock screens, rendered into a `DisplayText` without a
+//! heap.
+//!
+//! ```text
+//! %H  hour, 00 - 23       %I  hour, 01 - 12       %p  AM or PM
+//! %M  minute              %S  second              %Z  zone, ie. EST
+//! %a  weekday, Mon        %b  month, Jan          %j  day of the year
+//! %d  day of the month    %e  same, space padded  %m  month, 01 - 12
+//! %y  year, 00 - 99       %Y  year                %D  days left
+//! %T  temperature         %U  temperature unit
+//! %%  a literal %         %.  a literal . in its own cell
+//! ```
+//!
+//! Numbers are zero padded, except `%T` which is space padded. `-` after the
+//! `%` drops the padding and `_` pads with spaces instead, ie. `%-d` or
+//! `%_H`. A plain `.` lights the dot of the cell before it, so `%I.%M` shows
+//! as four cells. On countdown screens and in the countdown mode `%D`, `%H`,
+//! `%M` and `%S` are the time left instead, and the time run in the stopwatch
+//! mode, see `timer`.
+
+use super::{weekday_name, Datelike, NaiveDateTime, TempUnit, Timelike};
+use crate::alphanum::text::DisplayText;
+use core::fmt::Write;
+
+/// `12.34.56 PM`
+pub const TIME_12H: &[u8] = b"%I.%M.%S %p";
+/// `12.34.56`
+pub const TIME_24H: &[u8] = b"%H.%M.%S";
+/// `Mon 01.26`
+pub const DATE: &[u8] = b"%a %m.%d";
+/// `Mon 01.26 72F`
+pub const DATE_WITH_TEMPERATURE: &[u8] = b"%a %m.%d %T%U";
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateError {
+    /// `%` followed by a character that isn't a field
+    UnknownField(u8),
+    /// `%` or a padding flag at the very end
+    Incomplete,
+}
+
+/// Values the fields of a template are filled from
+pub struct Context<'a> {
+    /// Local time
+    pub datetime: NaiveDateTime,
+    /// Zone abbreviation in effect
+    pub zone: &'a str,
+    pub temperature: Option<i16>,
+    pub temp_unit: TempUnit,
+    /// Seconds left on a countdown, or run on a stopwatch
+    pub remaining: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pad {
+    Default,
+    None,
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Literal(u8),
+    /// A `.` lighting the dot of the cell before it
+    Dot,
+    Field(Pad, u8),
+    /// `%` at the end, with the padding flag if there was one
+    Incomplete(Option<u8>),
+}
+
+struct Tokens<'a> {
+    template: &'a [u8],
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let (b, rest) = self.template.split_first()?;
+        self.template = rest;
+
+        match b {
+            b'%' => {}
+            b'.' => return Some(Token::Dot),
+            _ => return Some(Token::Literal(*b)),
+        }
+
+        let (pad, flag) = match self.template.first() {
+            Some(b'-') => (Pad::None, Some(b'-')),
+            Some(b'_') => (Pad::Space, Some(b'_')),
+            _ => (Pad::Default, None),
+        };
+
+        if flag.is_some() {
+            self.template = &self.template[1..];
+        }
+
+        match self.template.split_first() {
+            Some((field, rest)) => {
+                self.template = rest;
+                Some(Token::Field(pad, *field))
+            }
+            None => Some(Token::Incomplete(flag)),
+        }
+    }
+}
+
+fn tokens(template: &[u8]) -> Tokens<'_> {
+    Tokens { template }
+}
+
+/// Checks every field of `template` is known, so mistakes show up when a
+/// screen is set up rather than on the display
+pub fn validate(template: &[u8]) -> Result<(), TemplateError> {
+    for token in tokens(template) {
+        match token {
+            Token::Field(_, field) if !is_field(field) => {
+                return Err(TemplateError::UnknownField(field))
+            }
+            Token::Incomplete(_) => return Err(TemplateError::Incomplete),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `template` shows the temperature, so it only has to be read when
+/// needed
+pub fn uses_temperature(template: &[u8]) -> bool {
+    tokens(template).any(|token| matches!(token, Token::Field(_, b'T')))
+}
+
+/// Whether `template` shows the zone, so it only has to be looked up when
+/// needed
+pub fn uses_zone(template: &[u8]) -> bool {
+    tokens(template).any(|token| matches!(token, Token::Field(_, b'Z')))
+}
+
+fn is_field(field: u8) -> bool {
+    b"HIpMSZabjdemyYDTU%.".contains(&field)
+}
+
+/// Renders `template` after whatever `text` already holds. Unknown fields are
+/// shown as written, see `validate`.
+pub fn render(template: &[u8], context: &Context, text: &mut DisplayText) {
+    let datetime = &context.datetime;
+    let remaining = context.remaining.map(|remaining| remaining.max(0));
+
+    for token in tokens(template) {
+        let (pad, field) = match token {
+            Token::Literal(b) => {
+                text.push(b);
+                continue;
+            }
+            // A leading dot has no cell to light, so it gets its own
+            Token::Dot if text.is_empty() => {
+                text.push(b'.');
+                continue;
+            }
+            Token::Dot => {
+                text.dot_last();
+                continue;
+            }
+            Token::Field(pad, field) => (pad, field),
+            Token::Incomplete(flag) => {
+                text.push(b'%');
+                if let Some(flag) = flag {
+                    text.push(flag);
+                }
+                continue;
+            }
+        };
+
+        let number = |value: i64, text: &mut DisplayText| write_number(text, value, 2, b'0', pad);
+
+        let _ = match (field, remaining) {
+            (b'D', Some(remaining)) => {
+                write_number(text, remaining / SECONDS_PER_DAY, 1, b'0', pad)
+            }
+            (b'H', Some(remaining)) => number(remaining % SECONDS_PER_DAY / 3600, text),
+            (b'M', Some(remaining)) => number(remaining % 3600 / 60, text),
+            (b'S', Some(remaining)) => number(remaining % 60, text),
+            (b'D', None) => write_number(text, 0, 1, b'0', pad),
+            (b'H', _) => number(datetime.hour() as i64, text),
+            (b'I', _) => number(datetime.hour12().1 as i64, text),
+            (b'p', _) => text.write_str(if datetime.hour12().0 { "PM" } else { "AM" }),
+            (b'M', _) => number(datetime.minute() as i64, text),
+            (b'S', _) => number(datetime.second() as i64, text),
+            (b'Z', _) => text.write_str(context.zone),
+            (b'a', _) => text.write_str(weekday_name(&datetime.date())),
+            (b'b', _) => text.write_str(MONTHS[datetime.month0() as usize]),
+            (b'j', _) => write_number(text, datetime.ordinal() as i64, 3, b'0', pad),
+            (b'd', _) => number(datetime.day() as i64, text),
+            (b'e', _) => write_number(text, datetime.day() as i64, 2, b' ', pad),
+            (b'm', _) => number(datetime.month() as i64, text),
+            (b'y', _) => number(datetime.year().rem_euclid(100) as i64, text),
+            (b'Y', _) => write!(text, "{}", datetime.year()),
+            (b'T', _) => match context.temperature {
+                Some(temperature) => write_number(text, temperature as i64, 2, b' ', pad),
+                None => text.write_str("--"),
+            },
+            (b'U', _) => {
+                text.push(context.temp_unit.symbol());
+                Ok(())
+            }
+            (b'%', _) | (b'.', _) => {
+                text.push(field);
+                Ok(())
+            }
+            // Unknown fields are shown as written
+            (other, _) => {
+                text.push(b'%');
+                text.push(other);
+                Ok(())
+            }
+        };
+    }
+}
+
+/// `value` at least `width` cells wide, padded with `fill` unless `pad` says
+/// otherwise
+fn write_number(
+    text: &mut DisplayText,
+    value: i64,
+    width: usize,
+    fill: u8,
+    pad: Pad,
+) -> core::fmt::Result {
+    let fill = match pad {
+        Pad::Default => fill,
+        Pad::Space => b' ',
+        Pad::None => return write!(text, "{}", value),
+    };
+
+    if fill == b'0' {
+        write!(text, "{:01$}", value, width)
+    } else {
+        write!(text, "{:>1$}", value, width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtc::NaiveDate;
+
+    /// Monday 2026-01-05 07:08:09
+    fn context() -> Context<'static> {
+        Context {
+            datetime: NaiveDate::from_ymd(2026, 1, 5).and_hms(7, 8, 9),
+            zone: "EST",
+            temperature: Some(72),
+            temp_unit: TempUnit::Fahrenheit,
+            remaining: None,
+        }
+    }
+
+    fn rendered(template: &[u8], context: &Context) -> DisplayText {
+        let mut text = DisplayText::new();
+        render(template, context, &mut text);
+        text
+    }
+
+    #[test]
+    fn renders_the_examples() {
+        let context = context();
+
+        assert_eq!(rendered(b"%a %m%d %TF", &context).text(), b"Mon 0105 72F");
+        assert_eq!(rendered(TIME_12H, &context).text(), b"070809 AM");
+        assert_eq!(
+            rendered(DATE_WITH_TEMPERATURE, &context).text(),
+            b"Mon 0105 72F"
+        );
+        assert_eq!(
+            rendered(b"%Y %y %b %j %Z", &context).text(),
+            b"2026 26 Jan 005 EST"
+        );
+    }
+
+    #[test]
+    fn padding_flags() {
+        let context = context();
+
+        assert_eq!(rendered(b"%d", &context).text(), b"05");
+        assert_eq!(rendered(b"%-d", &context).text(), b"5");
+        assert_eq!(rendered(b"%_d", &context).text(), b" 5");
+        assert_eq!(rendered(b"%e", &context).text(), b" 5");
+        assert_eq!(rendered(b"%-e", &context).text(), b"5");
+        assert_eq!(rendered(b"%H", &context).text(), b"07");
+        assert_eq!(rendered(b"%_H", &context).text(), b" 7");
+        assert_eq!(rendered(b"%-H", &context).text(), b"7");
+        assert_eq!(rendered(b"%-j", &context).text(), b"5");
+    }
+
+    #[test]
+    fn incomplete_and_unknown_fields() {
+        let context = context();
+
+        assert_eq!(validate(b"%H%"), Err(TemplateError::Incomplete));
+        assert_eq!(validate(b"%H%-"), Err(TemplateError::Incomplete));
+        assert_eq!(validate(b"%_"), Err(TemplateError::Incomplete));
+        assert_eq!(validate(b"%q"), Err(TemplateError::UnknownField(b'q')));
+        assert_eq!(validate(b"%a %m%d %TF"), Ok(()));
+
+        // Shown as written
+        assert_eq!(rendered(b"%H%", &context).text(), b"07%");
+        assert_eq!(rendered(b"%H%-", &context).text(), b"07%-");
+        assert_eq!(rendered(b"%q", &context).text(), b"%q");
+    }
+
+    #[test]
+    fn dots() {
+        let context = context();
+
+        let time = rendered(b"%I.%M", &context);
+        assert_eq!(time.text(), b"0708");
+        assert_eq!(time.dots(), [false, true, false, false]);
+
+        // A leading dot has no cell before it
+        let leading = rendered(b".%M", &context);
+        assert_eq!(leading.text(), b".08");
+        assert_eq!(leading.dots(), [false; 3]);
+
+        let literal = rendered(b"%H%.%M", &context);
+        assert_eq!(literal.text(), b"07.08");
+        assert_eq!(literal.dots(), [false; 5]);
+
+        assert_eq!(rendered(b"100%%", &context).text(), b"100%");
+    }
+
+    #[test]
+    fn temperature() {
+        let mut context = context();
+
+        context.temperature = Some(7);
+        assert_eq!(rendered(b"%T", &context).text(), b" 7");
+        assert_eq!(rendered(b"%-T", &context).text(), b"7");
+
+        context.temperature = Some(-5);
+        assert_eq!(rendered(b"%T%U", &context).text(), b"-5F");
+
+        context.temperature = Some(-12);
+        assert_eq!(rendered(b"%T", &context).text(), b"-12");
+
+        context.temperature = None;
+        context.temp_unit = TempUnit::Celsius;
+        assert_eq!(rendered(b"%T%U", &context).text(), b"--C");
+
+        assert!(uses_temperature(b"%a %T"));
+        assert!(!uses_temperature(b"%%T"));
+        assert!(uses_zone(b"%H %Z"));
+        assert!(!uses_zone(b"%H"));
+    }
+
+    #[test]
+    fn countdown() {
+        let mut context = context();
+
+        context.remaining = Some(2 * SECONDS_PER_DAY + 3 * 3600 + 4 * 60 + 5);
+        let left = rendered(b"%D.%H.%M.%S", &context);
+        assert_eq!(left.text(), b"2030405");
+        assert_eq!(left.dots(), [true, false, true, false, true, false, false]);
+
+        // Past the target shows zero rather than counting up
+        context.remaining = Some(-30);
+        let over = rendered(b"%D.%H.%M.%S", &context);
+        assert_eq!(over.text(), b"0000000");
+        assert_eq!(over.dots(), [true, false, true, false, true, false, false]);
+
+        // Without a countdown `%D` is 0 and the rest is the time
+        context.remaining = None;
+        assert_eq!(rendered(b"%D %H%M%S", &context).text(), b"0 070809");
+    }
+}
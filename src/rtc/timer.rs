@@ -0,0 +1,472 @@
+//! Countdown and stopwatch modes. Both run off the time source, so they keep
+//! whole seconds and survive resets: the countdown target, the stopwatch
+//! start and the mode shown are saved to flash, laps aren't.
+//!
+//! Times are held in UTC so DST changes don't move them, the countdown
+//! target is set in local time like alarms. Two buttons work the usual way:
+//! start/stop, and lap while running or reset while stopped.
+
+use super::source::TimeSource;
+use super::template::{self, Context};
+use super::{Clock, Error, NaiveDateTime};
+use crate::alphanum::text::DisplayText;
+use crate::flash::{QspiFlash, TIMER_SECTOR};
+use core::fmt::Write;
+use heapless::Vec;
+
+pub const MAX_LAPS: usize = 16;
+/// How long a lap is shown after it's taken
+pub const LAP_SHOW_SECS: i64 = 3;
+
+/// `12.03.04.05`, days then time
+pub const COUNTDOWN: &[u8] = b"%D.%H.%M.%S";
+/// `01.23.45`
+pub const STOPWATCH: &[u8] = b"%H.%M.%S";
+/// Shown in place of the countdown when there's no target
+pub const NO_COUNTDOWN: &str = "NO TIMER";
+
+/// Mode, then the countdown and stopwatch state. The countdown flag is 2
+/// once it finished, so it isn't reported again after a reset.
+const RECORD_LEN: usize = 27;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerError {
+    /// No countdown target is set
+    NoCountdown,
+    AlreadyRunning,
+    NotRunning,
+    TooManyLaps,
+}
+
+/// What the display shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The screen playlist, see `schedule`
+    Clock,
+    Countdown,
+    Stopwatch,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Clock => "clock",
+            Mode::Countdown => "countdown",
+            Mode::Stopwatch => "stopwatch",
+        }
+    }
+
+    /// Cycles clock, countdown, stopwatch
+    pub fn next(self) -> Self {
+        match self {
+            Mode::Clock => Mode::Countdown,
+            Mode::Countdown => Mode::Stopwatch,
+            Mode::Stopwatch => Mode::Clock,
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            Mode::Clock => 0,
+            Mode::Countdown => 1,
+            Mode::Stopwatch => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Mode> {
+        match id {
+            0 => Some(Mode::Clock),
+            1 => Some(Mode::Countdown),
+            2 => Some(Mode::Stopwatch),
+            _ => None,
+        }
+    }
+}
+
+/// Keys the modes are controlled with, ie. mapped from `Keypad` key numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    StartStop,
+    LapReset,
+    /// Next mode
+    Mode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Countdown {
+    /// UTC
+    target: Option<NaiveDateTime>,
+    /// Whether `take_finished` already reported reaching the target
+    finished: bool,
+}
+
+impl Countdown {
+    /// Counts down to `target`, UTC
+    pub fn set(&mut self, target: &NaiveDateTime) {
+        self.target = Some(*target);
+        self.finished = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.target = None;
+    }
+
+    /// UTC target
+    pub fn target(&self) -> Option<NaiveDateTime> {
+        self.target
+    }
+
+    /// Seconds left at `now`, UTC, 0 once the target has passed
+    pub fn remaining(&self, now: &NaiveDateTime) -> Option<i64> {
+        self.target
+            .map(|target| (target.timestamp() - now.timestamp()).max(0))
+    }
+
+    /// Whether the target was reached, only once per target
+    pub fn take_finished(&mut self, now: &NaiveDateTime) -> bool {
+        if self.finished || self.remaining(now) != Some(0) {
+            return false;
+        }
+
+        self.finished = true;
+
+        true
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stopwatch {
+    /// UTC time it was last started, while running
+    start: Option<NaiveDateTime>,
+    /// Seconds run before `start`
+    elapsed: i64,
+    /// Elapsed seconds at each lap
+    laps: Vec<i64, MAX_LAPS>,
+    /// UTC time of the last lap, to show it for a while
+    lap_at: Option<NaiveDateTime>,
+}
+
+impl Stopwatch {
+    pub fn is_running(&self) -> bool {
+        self.start.is_some()
+    }
+
+    /// Seconds run, up to `now`, UTC
+    pub fn elapsed(&self, now: &NaiveDateTime) -> i64 {
+        match self.start {
+            Some(start) => self.elapsed + (now.timestamp() - start.timestamp()).max(0),
+            None => self.elapsed,
+        }
+    }
+
+    pub fn laps(&self) -> &[i64] {
+        &self.laps
+    }
+
+    pub fn start(&mut self, now: &NaiveDateTime) -> Result<(), TimerError> {
+        if self.is_running() {
+            return Err(TimerError::AlreadyRunning);
+        }
+
+        self.start = Some(*now);
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self, now: &NaiveDateTime) -> Result<(), TimerError> {
+        if !self.is_running() {
+            return Err(TimerError::NotRunning);
+        }
+
+        self.elapsed = self.elapsed(now);
+        self.start = None;
+
+        Ok(())
+    }
+
+    /// Records the elapsed time, returning it
+    pub fn lap(&mut self, now: &NaiveDateTime) -> Result<i64, TimerError> {
+        if !self.is_running() {
+            return Err(TimerError::NotRunning);
+        }
+
+        let elapsed = self.elapsed(now);
+        self.laps
+            .push(elapsed)
+            .map_err(|_| TimerError::TooManyLaps)?;
+        self.lap_at = Some(*now);
+
+        Ok(elapsed)
+    }
+
+    /// Back to zero, stopping it and dropping the laps
+    pub fn reset(&mut self) {
+        *self = Stopwatch::default();
+    }
+
+    /// Number and time of the lap taken in the last `LAP_SHOW_SECS`
+    fn shown_lap(&self, now: &NaiveDateTime) -> Option<(usize, i64)> {
+        let lap_at = self.lap_at?;
+
+        if now.timestamp() - lap_at.timestamp() >= LAP_SHOW_SECS {
+            return None;
+        }
+
+        Some((self.laps.len(), *self.laps.last()?))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Timer {
+    pub mode: Mode,
+    pub countdown: Countdown,
+    pub stopwatch: Stopwatch,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Timer::new()
+    }
+}
+
+impl Timer {
+    /// Showing the clock, with no countdown set
+    pub fn new() -> Self {
+        Timer {
+            mode: Mode::Clock,
+            countdown: Countdown::default(),
+            stopwatch: Stopwatch::default(),
+        }
+    }
+
+    /// Handles a press of `button` at `now`, UTC. Buttons only work the
+    /// stopwatch, so they're ignored in other modes apart from `Mode`.
+    pub fn press(&mut self, button: Button, now: &NaiveDateTime) -> Result<(), TimerError> {
+        let stopwatch = &mut self.stopwatch;
+
+        match (button, self.mode) {
+            (Button::Mode, _) => self.mode = self.mode.next(),
+            (Button::StartStop, Mode::Stopwatch) if stopwatch.is_running() => {
+                stopwatch.stop(now)?
+            }
+            (Button::StartStop, Mode::Stopwatch) => stopwatch.start(now)?,
+            (Button::LapReset, Mode::Stopwatch) if stopwatch.is_running() => {
+                stopwatch.lap(now)?;
+            }
+            (Button::LapReset, Mode::Stopwatch) => stopwatch.reset(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// The countdown or stopwatch, `width` cells wide, `None` in
+    /// `Mode::Clock`. A lap just taken shows with its number in front, or as
+    /// just its number when that doesn't fit.
+    pub fn render<S: TimeSource>(
+        &self,
+        clock: &mut Clock<S>,
+        width: usize,
+    ) -> Result<Option<DisplayText>, Error<S::Error>> {
+        let now = clock.datetime()?;
+        let mut text = DisplayText::new();
+        let mut lap_number = None;
+
+        let (template, seconds) = match self.mode {
+            Mode::Clock => return Ok(None),
+            Mode::Countdown => match self.countdown.remaining(&now) {
+                Some(remaining) => (COUNTDOWN, remaining),
+                None => {
+                    text.push_str(NO_COUNTDOWN);
+                    return Ok(Some(text));
+                }
+            },
+            Mode::Stopwatch => match self.stopwatch.shown_lap(&now) {
+                Some((number, lap)) => {
+                    let _ = write!(text, "L{} ", number);
+                    lap_number = Some(number);
+                    (STOPWATCH, lap)
+                }
+                None => (STOPWATCH, self.stopwatch.elapsed(&now)),
+            },
+        };
+
+        let context = Context {
+            remaining: Some(seconds),
+            ..clock.context(template)?
+        };
+
+        template::render(template, &context, &mut text);
+
+        if let Some(number) = lap_number {
+            if text.len() > width {
+                text.clear();
+                let _ = write!(text, "LAP {}", number);
+            }
+        }
+
+        Ok(Some(text))
+    }
+
+    /// Loads the state saved with `save`, `None` if there isn't a valid one
+    pub fn load(flash: &mut QspiFlash) -> Option<Timer> {
+        let mut record = [0; RECORD_LEN];
+
+        if flash.load_record(TIMER_SECTOR, &mut record)? != RECORD_LEN {
+            return None;
+        }
+
+        let timestamp = |bytes: &[u8]| {
+            let mut timestamp = [0; 8];
+            timestamp.copy_from_slice(bytes);
+            i64::from_le_bytes(timestamp)
+        };
+        let datetime = |bytes: &[u8]| NaiveDateTime::from_timestamp_opt(timestamp(bytes), 0);
+
+        let mut timer = Timer::new();
+        timer.mode = Mode::from_id(record[0])?;
+
+        if record[1] != 0 {
+            timer.countdown.set(&datetime(&record[2..10])?);
+            timer.countdown.finished = record[1] == 2;
+        }
+
+        if record[10] != 0 {
+            timer.stopwatch.start = Some(datetime(&record[11..19])?);
+        }
+
+        timer.stopwatch.elapsed = timestamp(&record[19..27]);
+
+        Some(timer)
+    }
+
+    pub fn save(&self, flash: &mut QspiFlash) {
+        let mut record = [0; RECORD_LEN];
+
+        record[0] = self.mode.id();
+
+        if let Some(target) = self.countdown.target {
+            record[1] = if self.countdown.finished { 2 } else { 1 };
+            record[2..10].copy_from_slice(&target.timestamp().to_le_bytes());
+        }
+
+        if let Some(start) = self.stopwatch.start {
+            record[10] = 1;
+            record[11..19].copy_from_slice(&start.timestamp().to_le_bytes());
+        }
+
+        record[19..27].copy_from_slice(&self.stopwatch.elapsed.to_le_bytes());
+
+        flash.save_record(TIMER_SECTOR, &record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `seconds` after 2026-01-05 00:00:00 UTC
+    fn at(seconds: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp_opt(1_767_571_200 + seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn stopwatch_start_stop_lap_reset() {
+        let mut stopwatch = Stopwatch::default();
+
+        assert!(!stopwatch.is_running());
+        assert_eq!(stopwatch.elapsed(&at(0)), 0);
+        assert_eq!(stopwatch.stop(&at(0)), Err(TimerError::NotRunning));
+        assert_eq!(stopwatch.lap(&at(0)), Err(TimerError::NotRunning));
+
+        stopwatch.start(&at(10)).unwrap();
+        assert_eq!(stopwatch.start(&at(11)), Err(TimerError::AlreadyRunning));
+        assert_eq!(stopwatch.elapsed(&at(15)), 5);
+
+        // Shown for `LAP_SHOW_SECS`
+        assert_eq!(stopwatch.lap(&at(20)), Ok(10));
+        assert_eq!(stopwatch.shown_lap(&at(22)), Some((1, 10)));
+        assert_eq!(stopwatch.shown_lap(&at(23)), None);
+
+        stopwatch.stop(&at(30)).unwrap();
+        assert_eq!(stopwatch.elapsed(&at(100)), 20);
+
+        // Carries on from where it stopped
+        stopwatch.start(&at(200)).unwrap();
+        assert_eq!(stopwatch.lap(&at(205)), Ok(25));
+        assert_eq!(stopwatch.laps(), [10, 25]);
+
+        // The time being set back doesn't count backwards
+        assert_eq!(stopwatch.elapsed(&at(190)), 20);
+
+        stopwatch.reset();
+        assert!(!stopwatch.is_running());
+        assert_eq!(stopwatch.elapsed(&at(300)), 0);
+        assert!(stopwatch.laps().is_empty());
+        assert_eq!(stopwatch.shown_lap(&at(205)), None);
+    }
+
+    #[test]
+    fn stopwatch_lap_limit() {
+        let mut stopwatch = Stopwatch::default();
+        stopwatch.start(&at(0)).unwrap();
+
+        for lap in 0..MAX_LAPS {
+            assert_eq!(stopwatch.lap(&at(lap as i64)), Ok(lap as i64));
+        }
+
+        assert_eq!(stopwatch.lap(&at(100)), Err(TimerError::TooManyLaps));
+        assert_eq!(stopwatch.laps().len(), MAX_LAPS);
+    }
+
+    #[test]
+    fn countdown_finishes_once() {
+        let mut countdown = Countdown::default();
+
+        assert_eq!(countdown.remaining(&at(0)), None);
+        assert!(!countdown.take_finished(&at(0)));
+
+        countdown.set(&at(60));
+        assert_eq!(countdown.remaining(&at(0)), Some(60));
+        assert!(!countdown.take_finished(&at(59)));
+        assert!(countdown.take_finished(&at(60)));
+        assert!(!countdown.take_finished(&at(61)));
+        assert_eq!(countdown.remaining(&at(61)), Some(0));
+
+        // A new target is reported again, even if it's only noticed late
+        countdown.set(&at(120));
+        assert!(countdown.take_finished(&at(200)));
+
+        countdown.clear();
+        assert_eq!(countdown.target(), None);
+        assert!(!countdown.take_finished(&at(300)));
+    }
+
+    #[test]
+    fn buttons() {
+        let mut timer = Timer::new();
+
+        // Only the mode button works outside the stopwatch
+        timer.press(Button::StartStop, &at(0)).unwrap();
+        assert!(!timer.stopwatch.is_running());
+
+        timer.press(Button::Mode, &at(0)).unwrap();
+        assert_eq!(timer.mode, Mode::Countdown);
+        timer.press(Button::Mode, &at(0)).unwrap();
+        assert_eq!(timer.mode, Mode::Stopwatch);
+
+        timer.press(Button::StartStop, &at(0)).unwrap();
+        timer.press(Button::LapReset, &at(5)).unwrap();
+        timer.press(Button::StartStop, &at(10)).unwrap();
+        assert!(!timer.stopwatch.is_running());
+        assert_eq!(timer.stopwatch.laps(), [5]);
+        assert_eq!(timer.stopwatch.elapsed(&at(20)), 10);
+
+        timer.press(Button::LapReset, &at(20)).unwrap();
+        assert_eq!(timer.stopwatch.elapsed(&at(20)), 0);
+        assert!(timer.stopwatch.laps().is_empty());
+
+        timer.press(Button::Mode, &at(20)).unwrap();
+        assert_eq!(timer.mode, Mode::Clock);
+    }
+}
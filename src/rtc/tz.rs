@@ -0,0 +1,616 @@
+//! POSIX TZ rules, ie. `EST5EDT,M3.2.0,M11.1.0`, for turning the UTC held by
+//! the DS3231 into local time. Plain date arithmetic with no hardware access.
+//!
+//! Supported: quoted (`<+03>`) and alphabetic names, `[+-]hh[:mm[:ss]]`
+//! offsets (west of UTC is positive, as in POSIX), and `Mm.w.d`, `Jn` and `n`
+//! transition rules with optional `/time`. A DST zone without rules uses the
+//! US ones.
+
+use super::{Datelike, NaiveDate, NaiveDateTime};
+use heapless::Vec;
+
+pub const MAX_TZ_LEN: usize = 64;
+const MAX_NAME_LEN: usize = 8;
+
+const SECONDS_PER_HOUR: i32 = 3600;
+/// Transitions happen at 02:00 local time unless the rule says otherwise
+const DEFAULT_TRANSITION_TIME: i32 = 2 * SECONDS_PER_HOUR;
+const DEFAULT_RULES: &[u8] = b"M3.2.0,M11.1.0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TzError {
+    TooLong,
+    InvalidName,
+    InvalidOffset,
+    InvalidRule,
+    /// Unparsed input after a complete rule
+    TrailingInput,
+}
+
+/// Day of the year a transition happens on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    /// `Jn`: day 1 - 365, February 29th is never counted
+    JulianNoLeap(u16),
+    /// `n`: day 0 - 365, counting February 29th
+    Julian(u16),
+    /// `Mm.w.d`: weekday `d` (0 is Sunday) of week `w` (5 is the last) of
+    /// month `m`
+    MonthWeekDay { month: u8, week: u8, weekday: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Transition {
+    rule: Rule,
+    /// Local time of day, in seconds
+    time: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Dst {
+    name: Vec<u8, MAX_NAME_LEN>,
+    /// Seconds east of UTC
+    offset: i32,
+    start: Transition,
+    end: Transition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+    source: Vec<u8, MAX_TZ_LEN>,
+    name: Vec<u8, MAX_NAME_LEN>,
+    /// Seconds east of UTC
+    offset: i32,
+    dst: Option<Dst>,
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        TimeZone::utc()
+    }
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        let mut source = Vec::new();
+        let _ = source.extend_from_slice(b"UTC0");
+        let mut name = Vec::new();
+        let _ = name.extend_from_slice(b"UTC");
+
+        TimeZone {
+            source,
+            name,
+            offset: 0,
+            dst: None,
+        }
+    }
+
+    pub fn parse(tz: &[u8]) -> Result<TimeZone, TzError> {
+        let mut source = Vec::new();
+        source.extend_from_slice(tz).map_err(|_| TzError::TooLong)?;
+
+        let mut parser = Parser { s: tz, pos: 0 };
+
+        let name = parser.name()?;
+        let offset = -parser.offset().ok_or(TzError::InvalidOffset)?;
+
+        let dst = if parser.is_done() {
+            None
+        } else {
+            let dst_name = parser.name()?;
+
+            // DST is an hour ahead unless given
+            let dst_offset = match parser.peek() {
+                Some(b) if *b != b',' => -parser.offset().ok_or(TzError::InvalidOffset)?,
+                _ => offset + SECONDS_PER_HOUR,
+            };
+
+            let (start, end) = if parser.is_done() {
+                Parser {
+                    s: DEFAULT_RULES,
+                    pos: 0,
+                }
+                .rules()?
+            } else {
+                if !parser.eat(b',') {
+                    return Err(TzError::InvalidRule);
+                }
+                parser.rules()?
+            };
+
+            Some(Dst {
+                name: dst_name,
+                offset: dst_offset,
+                start,
+                end,
+            })
+        };
+
+        if !parser.is_done() {
+            return Err(TzError::TrailingInput);
+        }
+
+        Ok(TimeZone {
+            source,
+            name,
+            offset,
+            dst,
+        })
+    }
+
+    /// The rule as given to `parse`
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Whether daylight saving time is in effect at `utc`
+    pub fn is_dst(&self, utc: &NaiveDateTime) -> bool {
+        let dst = match &self.dst {
+            Some(dst) => dst,
+            None => return false,
+        };
+
+        let t = utc.timestamp();
+
+        // Transitions of the local year, which can differ from the UTC year
+        // around new year
+        let year = NaiveDateTime::from_timestamp_opt(t + self.offset as i64, 0)
+            .map_or(utc.year(), |local| local.year());
+
+        let start = transition_utc(&dst.start, year, self.offset);
+        let end = transition_utc(&dst.end, year, dst.offset);
+
+        if start < end {
+            t >= start && t < end
+        } else {
+            // Southern hemisphere, DST spans new year
+            t >= start || t < end
+        }
+    }
+
+    /// Seconds east of UTC in effect at `utc`
+    pub fn offset_at(&self, utc: &NaiveDateTime) -> i32 {
+        match &self.dst {
+            Some(dst) if self.is_dst(utc) => dst.offset,
+            _ => self.offset,
+        }
+    }
+
+    /// Zone abbreviation in effect at `utc`, ie. `EDT`
+    pub fn name_at(&self, utc: &NaiveDateTime) -> &str {
+        let name = match &self.dst {
+            Some(dst) if self.is_dst(utc) => &dst.name,
+            _ => &self.name,
+        };
+
+        // Names are checked to be ASCII when parsed
+        core::str::from_utf8(name).unwrap_or("")
+    }
+
+    pub fn to_local(&self, utc: &NaiveDateTime) -> NaiveDateTime {
+        let local = utc.timestamp() + self.offset_at(utc) as i64;
+
+        NaiveDateTime::from_timestamp_opt(local, 0).unwrap_or(*utc)
+    }
+
+    /// Inverse of `to_local`. Local times skipped or repeated by a DST change
+    /// can resolve to either offset.
+    pub fn to_utc(&self, local: &NaiveDateTime) -> NaiveDateTime {
+        let t = local.timestamp();
+
+        // Standard time is close enough to look up the offset in effect
+        let standard =
+            NaiveDateTime::from_timestamp_opt(t - self.offset as i64, 0).unwrap_or(*local);
+        let utc = t - self.offset_at(&standard) as i64;
+
+        NaiveDateTime::from_timestamp_opt(utc, 0).unwrap_or(*local)
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&u8> {
+        self.s.get(self.pos)
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.s.len()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(&b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn name(&mut self) -> Result<Vec<u8, MAX_NAME_LEN>, TzError> {
+        let mut name = Vec::new();
+
+        if self.eat(b'<') {
+            while let Some(b) = self.peek().copied() {
+                self.pos += 1;
+
+                if b == b'>' {
+                    return if name.len() >= 3 {
+                        Ok(name)
+                    } else {
+                        Err(TzError::InvalidName)
+                    };
+                }
+
+                if !(b.is_ascii_alphanumeric() || b == b'+' || b == b'-') {
+                    return Err(TzError::InvalidName);
+                }
+
+                name.push(b).map_err(|_| TzError::InvalidName)?;
+            }
+
+            return Err(TzError::InvalidName);
+        }
+
+        while let Some(b) = self.peek().copied() {
+            if !b.is_ascii_alphabetic() {
+                break;
+            }
+
+            name.push(b).map_err(|_| TzError::InvalidName)?;
+            self.pos += 1;
+        }
+
+        if name.len() >= 3 {
+            Ok(name)
+        } else {
+            Err(TzError::InvalidName)
+        }
+    }
+
+    fn number(&mut self, max_digits: usize) -> Option<u32> {
+        let start = self.pos;
+        let mut n = 0u32;
+
+        while let Some(b) = self.peek().copied() {
+            if !b.is_ascii_digit() || self.pos - start == max_digits {
+                break;
+            }
+
+            n = n * 10 + (b - b'0') as u32;
+            self.pos += 1;
+        }
+
+        if self.pos > start {
+            Some(n)
+        } else {
+            None
+        }
+    }
+
+    /// `[+-]hh[:mm[:ss]]` in seconds, up to 167 hours
+    fn offset(&mut self) -> Option<i32> {
+        let negative = if self.eat(b'-') {
+            true
+        } else {
+            self.eat(b'+');
+            false
+        };
+
+        let hours = self.number(3)?;
+        let mut seconds = hours * 3600;
+
+        if self.eat(b':') {
+            let minutes = self.number(2)?;
+            seconds += minutes * 60;
+
+            if self.eat(b':') {
+                seconds += self.number(2)?;
+            }
+        }
+
+        if hours > 167 {
+            return None;
+        }
+
+        let seconds = seconds as i32;
+        Some(if negative { -seconds } else { seconds })
+    }
+
+    fn rules(&mut self) -> Result<(Transition, Transition), TzError> {
+        let start = self.transition()?;
+
+        if !self.eat(b',') {
+            return Err(TzError::InvalidRule);
+        }
+
+        let end = self.transition()?;
+
+        Ok((start, end))
+    }
+
+    fn transition(&mut self) -> Result<Transition, TzError> {
+        let rule = if self.eat(b'M') {
+            let month = self.number(2).ok_or(TzError::InvalidRule)?;
+            if !self.eat(b'.') {
+                return Err(TzError::InvalidRule);
+            }
+            let week = self.number(1).ok_or(TzError::InvalidRule)?;
+            if !self.eat(b'.') {
+                return Err(TzError::InvalidRule);
+            }
+            let weekday = self.number(1).ok_or(TzError::InvalidRule)?;
+
+            if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+                return Err(TzError::InvalidRule);
+            }
+
+            Rule::MonthWeekDay {
+                month: month as u8,
+                week: week as u8,
+                weekday: weekday as u8,
+            }
+        } else if self.eat(b'J') {
+            let day = self.number(3).ok_or(TzError::InvalidRule)?;
+
+            if !(1..=365).contains(&day) {
+                return Err(TzError::InvalidRule);
+            }
+
+            Rule::JulianNoLeap(day as u16)
+        } else {
+            let day = self.number(3).ok_or(TzError::InvalidRule)?;
+
+            if day > 365 {
+                return Err(TzError::InvalidRule);
+            }
+
+            Rule::Julian(day as u16)
+        };
+
+        let time = if self.eat(b'/') {
+            self.offset().ok_or(TzError::InvalidRule)?
+        } else {
+            DEFAULT_TRANSITION_TIME
+        };
+
+        Ok(Transition { rule, time })
+    }
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Date a rule falls on in `year`
+fn rule_date(rule: &Rule, year: i32) -> NaiveDate {
+    let date = match *rule {
+        Rule::JulianNoLeap(day) => {
+            let leap_day = if is_leap_year(year) && day >= 60 {
+                1
+            } else {
+                0
+            };
+            NaiveDate::from_yo_opt(year, day as u32 + leap_day)
+        }
+        Rule::Julian(day) => NaiveDate::from_yo_opt(year, day as u32 + 1),
+        Rule::MonthWeekDay {
+            month,
+            week,
+            weekday,
+        } => {
+            let month = month as u32;
+            let first = NaiveDate::from_ymd_opt(year, month, 1);
+
+            first.and_then(|first| {
+                let first_weekday = first.weekday().num_days_from_sunday();
+                let mut day = 1 + (weekday as u32 + 7 - first_weekday) % 7 + (week as u32 - 1) * 7;
+
+                // Week 5 is the last one, which may only have 4
+                while day > days_in_month(year, month) {
+                    day -= 7;
+                }
+
+                NaiveDate::from_ymd_opt(year, month, day)
+            })
+        }
+    };
+
+    // Day 366 of a non leap year rolls into the next year
+    date.unwrap_or_else(|| NaiveDate::from_ymd(year, 12, 31))
+}
+
+/// Unix time a transition happens at, given the offset in effect before it
+fn transition_utc(transition: &Transition, year: i32, offset_before: i32) -> i64 {
+    let midnight = rule_date(&transition.rule, year)
+        .and_hms(0, 0, 0)
+        .timestamp();
+
+    midnight + transition.time as i64 - offset_before as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(year: i32, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(year, month, day).and_hms(hour, min, sec)
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(year, month, day)
+    }
+
+    #[test]
+    fn month_week_day_rules() {
+        let second_sunday = Rule::MonthWeekDay {
+            month: 3,
+            week: 2,
+            weekday: 0,
+        };
+        let last_sunday = Rule::MonthWeekDay {
+            month: 10,
+            week: 5,
+            weekday: 0,
+        };
+
+        assert_eq!(rule_date(&second_sunday, 2024), date(2024, 3, 10));
+        assert_eq!(rule_date(&second_sunday, 2025), date(2025, 3, 9));
+        // Week 5 is the last, October 2024 only has four Sundays
+        assert_eq!(rule_date(&last_sunday, 2024), date(2024, 10, 27));
+        assert_eq!(rule_date(&last_sunday, 2026), date(2026, 10, 25));
+    }
+
+    #[test]
+    fn julian_rules() {
+        // `Jn` never counts February 29th
+        assert_eq!(rule_date(&Rule::JulianNoLeap(59), 2024), date(2024, 2, 28));
+        assert_eq!(rule_date(&Rule::JulianNoLeap(60), 2024), date(2024, 3, 1));
+        assert_eq!(rule_date(&Rule::JulianNoLeap(60), 2023), date(2023, 3, 1));
+        assert_eq!(
+            rule_date(&Rule::JulianNoLeap(365), 2024),
+            date(2024, 12, 31)
+        );
+
+        // `n` does, from 0
+        assert_eq!(rule_date(&Rule::Julian(0), 2024), date(2024, 1, 1));
+        assert_eq!(rule_date(&Rule::Julian(59), 2024), date(2024, 2, 29));
+        assert_eq!(rule_date(&Rule::Julian(59), 2023), date(2023, 3, 1));
+        assert_eq!(rule_date(&Rule::Julian(365), 2023), date(2023, 12, 31));
+    }
+
+    #[test]
+    fn parses_rules() {
+        let zone = TimeZone::parse(b"<+03>-3").unwrap();
+        assert_eq!(zone.offset_at(&datetime(2024, 6, 1, 0, 0, 0)), 3 * 3600);
+        assert_eq!(zone.name_at(&datetime(2024, 6, 1, 0, 0, 0)), "+03");
+
+        let zone = TimeZone::parse(b"XXX3YYY,J60/0,300/1:30").unwrap();
+        assert!(!zone.is_dst(&datetime(2023, 3, 1, 2, 59, 59)));
+        assert!(zone.is_dst(&datetime(2023, 3, 1, 3, 0, 0)));
+
+        // A DST zone without rules uses the US ones
+        let zone = TimeZone::parse(b"EST5EDT").unwrap();
+        assert!(!zone.is_dst(&datetime(2024, 3, 10, 6, 59, 59)));
+        assert!(zone.is_dst(&datetime(2024, 3, 10, 7, 0, 0)));
+
+        assert_eq!(
+            TimeZone::parse(b"EST5EDT,M13.1.0,M11.1.0"),
+            Err(TzError::InvalidRule)
+        );
+        assert_eq!(
+            TimeZone::parse(b"EST5EDT,J0,M11.1.0"),
+            Err(TzError::InvalidRule)
+        );
+        assert_eq!(
+            TimeZone::parse(b"EST5EDT,366,M11.1.0"),
+            Err(TzError::InvalidRule)
+        );
+        assert_eq!(
+            TimeZone::parse(b"EST5EDT,M3.2.0"),
+            Err(TzError::InvalidRule)
+        );
+        assert_eq!(TimeZone::parse(b"EST5 "), Err(TzError::InvalidName));
+    }
+
+    #[test]
+    fn northern_transitions() {
+        let zone = TimeZone::parse(b"EST5EDT,M3.2.0,M11.1.0").unwrap();
+
+        // 02:00 EST on March 10th 2024 is 07:00 UTC
+        let start = datetime(2024, 3, 10, 7, 0, 0);
+        assert_eq!(
+            zone.to_local(&datetime(2024, 3, 10, 6, 59, 59)),
+            datetime(2024, 3, 10, 1, 59, 59)
+        );
+        assert_eq!(zone.to_local(&start), datetime(2024, 3, 10, 3, 0, 0));
+        assert_eq!(zone.name_at(&start), "EDT");
+
+        for utc in &[
+            datetime(2024, 3, 10, 6, 59, 59),
+            start,
+            datetime(2024, 3, 10, 7, 0, 1),
+        ] {
+            assert_eq!(zone.to_utc(&zone.to_local(utc)), *utc);
+        }
+
+        // 02:00 EDT on November 3rd 2024 is 06:00 UTC
+        let end = datetime(2024, 11, 3, 6, 0, 0);
+        assert_eq!(
+            zone.to_local(&datetime(2024, 11, 3, 5, 59, 59)),
+            datetime(2024, 11, 3, 1, 59, 59)
+        );
+        assert_eq!(zone.to_local(&end), datetime(2024, 11, 3, 1, 0, 0));
+        assert_eq!(zone.name_at(&end), "EST");
+
+        // Local times outside the repeated hour resolve one way
+        assert_eq!(
+            zone.to_utc(&datetime(2024, 11, 3, 0, 59, 59)),
+            datetime(2024, 11, 3, 4, 59, 59)
+        );
+        assert_eq!(
+            zone.to_utc(&datetime(2024, 11, 3, 2, 0, 0)),
+            datetime(2024, 11, 3, 7, 0, 0)
+        );
+        assert_eq!(
+            zone.to_utc(&datetime(2024, 11, 3, 2, 0, 1)),
+            datetime(2024, 11, 3, 7, 0, 1)
+        );
+    }
+
+    #[test]
+    fn southern_transitions() {
+        let zone = TimeZone::parse(b"AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+
+        // DST spans new year
+        assert!(zone.is_dst(&datetime(2024, 1, 15, 0, 0, 0)));
+        assert!(!zone.is_dst(&datetime(2024, 7, 15, 0, 0, 0)));
+        assert!(zone.is_dst(&datetime(2024, 12, 31, 14, 0, 0)));
+
+        // 03:00 AEDT on April 7th 2024 is 16:00 UTC the day before
+        let end = datetime(2024, 4, 6, 16, 0, 0);
+        assert_eq!(
+            zone.to_local(&datetime(2024, 4, 6, 15, 59, 59)),
+            datetime(2024, 4, 7, 2, 59, 59)
+        );
+        assert_eq!(zone.to_local(&end), datetime(2024, 4, 7, 2, 0, 0));
+        assert_eq!(zone.name_at(&end), "AEST");
+        assert_eq!(
+            zone.to_utc(&datetime(2024, 4, 7, 1, 59, 59)),
+            datetime(2024, 4, 6, 14, 59, 59)
+        );
+        assert_eq!(
+            zone.to_utc(&datetime(2024, 4, 7, 3, 0, 0)),
+            datetime(2024, 4, 6, 17, 0, 0)
+        );
+
+        // 02:00 AEST on October 6th 2024 is 16:00 UTC the day before
+        let start = datetime(2024, 10, 5, 16, 0, 0);
+        assert_eq!(
+            zone.to_local(&datetime(2024, 10, 5, 15, 59, 59)),
+            datetime(2024, 10, 6, 1, 59, 59)
+        );
+        assert_eq!(zone.to_local(&start), datetime(2024, 10, 6, 3, 0, 0));
+        assert_eq!(zone.name_at(&start), "AEDT");
+
+        for utc in &[
+            datetime(2024, 10, 5, 15, 59, 59),
+            start,
+            datetime(2024, 10, 5, 16, 0, 1),
+        ] {
+            assert_eq!(zone.to_utc(&zone.to_local(utc)), *utc);
+        }
+    }
+}